@@ -9,6 +9,7 @@ sharedir2=/usr/share/xdg-desktop-portal-pikeru
 dbusdir1=/usr/local/share/dbus-1/services
 dbusdir2=/usr/share/dbus-1/services
 mandir=/usr/local/share/man/man5
+appdir=/usr/local/share/applications
 portaldir=/usr/share/xdg-desktop-portal/portals
 portalfile=/usr/share/xdg-desktop-portal/portals/pikeru.portal
 confdir=$HOME/.config/xdg-desktop-portal-pikeru
@@ -20,6 +21,7 @@ manpage=xdg_portal/xdg-desktop-portal-pikeru.5.scd
 wrapper=xdg_portal/pikeru-wrapper.sh
 sd_svc=xdg_portal/xdg-desktop-portal-pikeru.service
 sample_conf=xdg_portal/config.in
+viewer=assets/pikeru-view.desktop
 
 get_desktop(){
 	[ -z "$XDG_CURRENT_DESKTOP" ] && return
@@ -29,7 +31,7 @@ get_desktop(){
 
 if [[ $(whoami) = root ]]; then
 	set -x
-	mkdir -p $mandir $dbusdir1 $dbusdir2 $sharedir1 $sharedir2 $portaldir
+	mkdir -p $mandir $appdir $dbusdir1 $dbusdir2 $sharedir1 $sharedir2 $portaldir
 	mv -u target/release/pikeru $bindir
 	mv -u target/release/portal $portalbin
 	cp -u $wrapper $sharedir1
@@ -37,6 +39,7 @@ if [[ $(whoami) = root ]]; then
 	cp -u $dbus_svc $dbusdir1
 	cp -u $dbus_svc $dbusdir2
 	cp -u $sd_svc $unitdir
+	cp -u $viewer $appdir
 	scdoc < $manpage > $mandir/xdg-desktop-portal-pikeru.5
 	sed "s/@cur_desktop@/$(get_desktop)/" xdg_portal/pikeru.portal.in > $portalfile
 else
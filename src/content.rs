@@ -0,0 +1,106 @@
+// Content search, which greps text files under the searched dirs instead of fuzzy
+// matching names. The picker shows the first matching line of each file under its
+// label, and 'pikeru search --content' prints them like grep -n.
+use std::{
+    fs::OpenOptions,
+    io::Read,
+    mem,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::UnboundedSender as USender;
+use crate::{SearchEvent, walk::WalkOpts, rawpath};
+
+/// Bigger files are skipped rather than read whole
+const MAX_SIZE: u64 = 16 << 20;
+/// Characters of a matching line kept for the snippet
+const SNIPPET_LEN: usize = 80;
+
+pub struct Grep {
+    pub dirs: Vec<String>,
+    /// paths from --stdin, which are searched instead of walking dirs
+    pub listed: Option<Vec<String>>,
+    pub recursive: bool,
+    pub term: String,
+    pub nav_id: u8,
+    pub walk: WalkOpts,
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Grep {
+    /// Blocks while walking, sending batches of (path, line number, snippet) as
+    /// SearchEvent::ContentMatches until the walk is done or cancelled
+    pub fn run(self, results: USender<SearchEvent>) {
+        let needle = self.term.to_lowercase();
+        let mut batch = vec![];
+        let mut last_send = Instant::now();
+        let send = |batch: &mut Vec<(String, usize, String)>| {
+            if !batch.is_empty() && !self.cancel.load(Ordering::Relaxed) {
+                let _ = results.send(SearchEvent::ContentMatches(mem::take(batch), self.nav_id, self.term.clone()));
+            }
+        };
+        let mut visit = |path: PathBuf| {
+            if self.cancel.load(Ordering::Relaxed) {
+                return false;
+            }
+            if let Some((line, snippet)) = find(&path, &needle) {
+                batch.push((rawpath::to_str(&path), line, snippet));
+            }
+            // stream matches so they show up while a big tree is still being walked
+            if last_send.elapsed() > Duration::from_millis(100) {
+                send(&mut batch);
+                last_send = Instant::now();
+            }
+            true
+        };
+        match &self.listed {
+            Some(paths) => {
+                let _ = paths.iter().all(|p| visit(rawpath::to_path(p).into_owned()));
+            },
+            None => self.walk.walk(&self.dirs, self.recursive, &mut visit),
+        }
+        send(&mut batch);
+    }
+}
+
+/// The 1-based number and a snippet of the first line containing needle, which must
+/// be lowercase. None when nothing matches or the file isn't text.
+pub fn find(path: &Path, needle: &str) -> Option<(usize, String)> {
+    // opening a FIFO would block until something writes to it
+    if !std::fs::metadata(path).ok()?.is_file() {
+        return None;
+    }
+    // and in case it was swapped for one since, opening doesn't wait
+    let file = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path).ok()?;
+    let meta = file.metadata().ok()?;
+    if !meta.is_file() || meta.len() > MAX_SIZE {
+        return None;
+    }
+    let mut buf = vec![];
+    file.take(MAX_SIZE).read_to_end(&mut buf).ok()?;
+    // a NUL byte near the start means binary, the same check grep and git use
+    if buf[..buf.len().min(8192)].contains(&0) {
+        return None;
+    }
+    String::from_utf8_lossy(&buf).lines().enumerate().find_map(|(i, line)| {
+        let lower = line.to_lowercase();
+        let pos = lower.find(needle)?;
+        // lowercasing rarely changes lengths, so the char offset is close enough for context
+        Some((i + 1, snippet(line, lower[..pos].chars().count(), SNIPPET_LEN)))
+    })
+}
+
+/// Trims text to len characters around the one at char index at
+pub fn snippet(txt: &str, at: usize, len: usize) -> String {
+    let txt = txt.trim_end();
+    let chars = txt.chars().count();
+    if chars <= len {
+        return txt.trim_start().to_string();
+    }
+    let start = at.saturating_sub(len / 4).min(chars - len);
+    let part = txt.chars().skip(start).take(len).collect::<String>();
+    format!("{}{}{}", if start > 0 { "..." } else { "" }, part.trim_start(),
+            if start + len < chars { "..." } else { "" })
+}
@@ -0,0 +1,360 @@
+// Minimal EXIF handling for JPEG, PNG and WebP. The portal strips metadata from uploads
+// and the picker warns about location data, so each binary only uses part of this.
+#![allow(dead_code)]
+
+// tags in the first IFD, and the GPS IFD it points to
+const ORIENTATION: u16 = 0x0112;
+const GPS_IFD: u16 = 0x8825;
+const GPS_LATITUDE: u16 = 0x0002;
+
+const PNG_SIG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    le: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        match data.get(0..2)? {
+            b"II" => Some(Self { data, le: true }),
+            b"MM" => Some(Self { data, le: false }),
+            _ => None,
+        }
+    }
+
+    fn u16_at(&self, i: usize) -> Option<u16> {
+        let b = self.data.get(i..i+2)?;
+        Some(if self.le { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) })
+    }
+
+    fn u32_at(&self, i: usize) -> Option<u32> {
+        let b = self.data.get(i..i+4)?;
+        Some(if self.le { u32::from_le_bytes([b[0], b[1], b[2], b[3]]) } else { u32::from_be_bytes([b[0], b[1], b[2], b[3]]) })
+    }
+
+    /// Returns the value of a short or long tag from the IFD at offset
+    fn tag(&self, ifd: usize, tag: u16) -> Option<u32> {
+        let count = self.u16_at(ifd)? as usize;
+        (0..count).map(|n| ifd + 2 + n * 12).find(|&entry| self.u16_at(entry) == Some(tag)).and_then(|entry| {
+            match self.u16_at(entry + 2)? {
+                3 => self.u16_at(entry + 8).map(|v| v as u32),
+                _ => self.u32_at(entry + 8),
+            }
+        })
+    }
+
+    fn ifd0_tag(&self, tag: u16) -> Option<u32> {
+        self.tag(self.u32_at(4)? as usize, tag)
+    }
+}
+
+/// Finds the TIFF structured EXIF block in a JPEG, PNG or WebP file
+fn tiff_block(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(&[0xff, 0xd8]) {
+        let mut i = 2;
+        while data.get(i) == Some(&0xff) {
+            if data.get(i + 1) == Some(&0xff) {
+                i += 1;
+                continue;
+            }
+            let marker = *data.get(i + 1)?;
+            if marker == 0xda || marker == 0xd9 {
+                break;
+            }
+            let len = u16::from_be_bytes([*data.get(i + 2)?, *data.get(i + 3)?]) as usize;
+            let seg = data.get(i + 4..i + 2 + len)?;
+            if marker == 0xe1 && seg.starts_with(b"Exif\0\0") {
+                return Some(&seg[6..]);
+            }
+            i += 2 + len;
+        }
+        None
+    } else if data.starts_with(PNG_SIG) {
+        let mut i = PNG_SIG.len();
+        while let Some(len) = data.get(i..i + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize) {
+            if data.get(i + 4..i + 8)? == b"eXIf" {
+                return data.get(i + 8..i + 8 + len);
+            }
+            i += 12 + len;
+        }
+        None
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        let mut i = 12;
+        while let Some(len) = data.get(i + 4..i + 8).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize) {
+            if data.get(i..i + 4)? == b"EXIF" {
+                let body = data.get(i + 8..i + 8 + len)?;
+                return Some(body.strip_prefix(b"Exif\0\0").unwrap_or(body));
+            }
+            i += 8 + len + (len & 1);
+        }
+        None
+    } else {
+        None
+    }
+}
+
+/// EXIF orientation from 1 to 8, where 1 means the pixels are already upright
+pub fn orientation(data: &[u8]) -> u16 {
+    tiff_block(data).and_then(Tiff::new).and_then(|t| t.ifd0_tag(ORIENTATION))
+        .map(|o| o as u16).filter(|o| (1..=8).contains(o)).unwrap_or(1)
+}
+
+pub fn has_gps(data: &[u8]) -> bool {
+    let Some(tiff) = tiff_block(data).and_then(Tiff::new) else { return false };
+    tiff.ifd0_tag(GPS_IFD).is_some_and(|gps| tiff.tag(gps as usize, GPS_LATITUDE).is_some())
+}
+
+pub enum Stripped {
+    /// There was no metadata to remove
+    Clean,
+    Removed(Vec<u8>),
+    /// Not a JPEG, PNG or WebP this could follow to the end, so metadata may still be in it
+    Unreadable,
+}
+
+/// Removes EXIF, XMP, IPTC and text metadata. ICC profiles are kept.
+pub fn strip(data: &[u8]) -> Stripped {
+    let parsed = if data.starts_with(&[0xff, 0xd8]) {
+        strip_jpeg(data)
+    } else if data.starts_with(PNG_SIG) {
+        strip_png(data)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        strip_webp(data)
+    } else {
+        None
+    };
+    match parsed {
+        Some((out, true)) => Stripped::Removed(out),
+        Some((_, false)) => Stripped::Clean,
+        None => Stripped::Unreadable,
+    }
+}
+
+/// The copy and whether anything was removed, or None if the file didn't parse
+fn strip_jpeg(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let mut out = data[..2].to_vec();
+    let mut removed = false;
+    let mut i = 2;
+    loop {
+        if *data.get(i)? != 0xff {
+            return None;
+        }
+        // a marker can be padded with any number of 0xff
+        while data.get(i + 1) == Some(&0xff) {
+            i += 1;
+        }
+        let marker = *data.get(i + 1)?;
+        match marker {
+            0xd9 => {
+                out.extend_from_slice(&[0xff, 0xd9]);
+                // trailers after the image can hold more metadata, or whole images with their own
+                removed |= i + 2 < data.len();
+                return Some((out, removed));
+            },
+            0x01 | 0xd0..=0xd7 => {
+                out.extend_from_slice(&[0xff, marker]);
+                i += 2;
+                continue;
+            },
+            _ => {},
+        }
+        let len = u16::from_be_bytes([*data.get(i + 2)?, *data.get(i + 3)?]) as usize;
+        if len < 2 {
+            return None;
+        }
+        let mut end = i + 2 + len;
+        data.get(i..end)?;
+        if marker == 0xda {
+            // scan data runs to the next marker that isn't a stuffed 0 or a restart
+            while *data.get(end)? != 0xff || matches!(*data.get(end + 1)?, 0x00 | 0xd0..=0xd7 | 0xff) {
+                end += 1;
+            }
+        }
+        // APP1 is EXIF and XMP, APP13 is IPTC and COM is free text
+        if matches!(marker, 0xe1 | 0xed | 0xfe) {
+            removed = true;
+        } else {
+            out.extend_from_slice(&data[i..end]);
+        }
+        i = end;
+    }
+}
+
+fn strip_png(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let mut out = PNG_SIG.to_vec();
+    let mut removed = false;
+    let mut i = PNG_SIG.len();
+    loop {
+        let len = u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?) as usize;
+        let end = i + 12 + len;
+        let kind = data.get(i + 4..i + 8)?;
+        if matches!(kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            removed = true;
+            data.get(i..end)?;
+        } else {
+            out.extend_from_slice(data.get(i..end)?);
+        }
+        if kind == b"IEND" {
+            removed |= end < data.len();
+            return Some((out, removed));
+        }
+        i = end;
+    }
+}
+
+fn strip_webp(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let mut out = data[..12].to_vec();
+    let mut removed = false;
+    let mut i = 12;
+    while i < data.len() {
+        let len = u32::from_le_bytes(data.get(i + 4..i + 8)?.try_into().ok()?) as usize;
+        if i + 8 + len > data.len() {
+            return None;
+        }
+        // chunks are padded to an even length, though some writers leave it off the last one
+        let end = (i + 8 + len + (len & 1)).min(data.len());
+        match &data[i..i + 4] {
+            b"EXIF" | b"XMP " => removed = true,
+            b"VP8X" => {
+                if len < 1 {
+                    return None;
+                }
+                let flags = out.len() + 8;
+                out.extend_from_slice(&data[i..end]);
+                // clear the flags announcing EXIF and XMP chunks
+                out[flags] &= !0x0c;
+            },
+            _ => out.extend_from_slice(&data[i..end]),
+        }
+        i = end;
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some((out, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little endian TIFF block with an orientation and a GPS IFD holding a latitude
+    fn tiff() -> Vec<u8> {
+        let mut t = b"II*\0\x08\0\0\0".to_vec();
+        t.extend_from_slice(&2u16.to_le_bytes());
+        t.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        t.extend_from_slice(&[0x25, 0x88, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+        t.extend_from_slice(&[0; 4]);
+        t.extend_from_slice(&1u16.to_le_bytes());
+        t.extend_from_slice(&[0x02, 0x00, 4, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        t
+    }
+
+    fn segment(marker: u8, body: &[u8]) -> Vec<u8> {
+        [&[0xff, marker][..], &(body.len() as u16 + 2).to_be_bytes(), body].concat()
+    }
+
+    fn jpeg(exif: bool) -> Vec<u8> {
+        let mut j = vec![0xff, 0xd8];
+        j.extend(segment(0xe0, b"JFIF\0"));
+        if exif {
+            // fill bytes before a marker are allowed
+            j.extend_from_slice(&[0xff, 0xff]);
+            j.extend(segment(0xe1, &[&b"Exif\0\0"[..], &tiff()].concat()));
+        }
+        j.extend(segment(0xdb, &[0; 4]));
+        j.extend(segment(0xda, &[0; 4]));
+        j.extend_from_slice(&[0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56, 0xff, 0xd9]);
+        j
+    }
+
+    fn png_chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        [&(body.len() as u32).to_be_bytes()[..], kind, body, &[0; 4]].concat()
+    }
+
+    fn png(text: bool) -> Vec<u8> {
+        let mut p = PNG_SIG.to_vec();
+        p.extend(png_chunk(b"IHDR", &[0; 13]));
+        if text {
+            p.extend(png_chunk(b"tEXt", b"Comment\0secret"));
+        }
+        p.extend(png_chunk(b"IDAT", &[1, 2, 3]));
+        p.extend(png_chunk(b"IEND", &[]));
+        p
+    }
+
+    fn webp(chunks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (kind, data) in chunks {
+            body.extend_from_slice(kind);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat()
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn reads_orientation_and_gps() {
+        let data = jpeg(true);
+        assert_eq!(orientation(&data), 6);
+        assert!(has_gps(&data));
+        assert_eq!(orientation(&jpeg(false)), 1);
+        assert!(!has_gps(&jpeg(false)));
+    }
+
+    #[test]
+    fn strips_jpeg() {
+        let Stripped::Removed(out) = strip(&jpeg(true)) else { panic!("not stripped") };
+        assert_eq!(out, jpeg(false));
+        assert!(matches!(strip(&jpeg(false)), Stripped::Clean));
+    }
+
+    #[test]
+    fn jpeg_trailer_is_removed() {
+        let mut data = jpeg(false);
+        data.extend_from_slice(b"Exif\0\0 hidden");
+        let Stripped::Removed(out) = strip(&data) else { panic!("not stripped") };
+        assert_eq!(out, jpeg(false));
+    }
+
+    #[test]
+    fn truncated_jpeg_is_unreadable() {
+        let data = jpeg(true);
+        for len in [3, 10, 30, data.len() - 1] {
+            assert!(matches!(strip(&data[..len]), Stripped::Unreadable), "{}", len);
+        }
+    }
+
+    #[test]
+    fn strips_png() {
+        let Stripped::Removed(out) = strip(&png(true)) else { panic!("not stripped") };
+        assert_eq!(out, png(false));
+        assert!(matches!(strip(&png(false)), Stripped::Clean));
+        let data = png(true);
+        assert!(matches!(strip(&data[..data.len() - 20]), Stripped::Unreadable));
+    }
+
+    #[test]
+    fn strips_webp() {
+        let exif = [&b"Exif\0\0"[..], &tiff()].concat();
+        let data = webp(&[(b"VP8X", &[0x0c, 0, 0, 0, 0, 0, 0, 0, 0, 0]), (b"VP8 ", &[1, 2, 3]), (b"EXIF", &exif)]);
+        assert!(has_gps(&data));
+        let Stripped::Removed(out) = strip(&data) else { panic!("not stripped") };
+        assert_eq!(out, webp(&[(b"VP8X", &[0; 10]), (b"VP8 ", &[1, 2, 3])]));
+        assert!(!contains(&out, b"Exif"));
+    }
+
+    #[test]
+    fn bad_webp_is_unreadable() {
+        assert!(matches!(strip(&webp(&[(b"VP8X", &[])])), Stripped::Unreadable));
+        let data = webp(&[(b"VP8 ", &[1, 2, 3]), (b"EXIF", &[0; 40])]);
+        assert!(matches!(strip(&data[..data.len() - 10]), Stripped::Unreadable));
+    }
+}
@@ -0,0 +1,148 @@
+// Limits on which items the picker shows, set from the Filter menu and saved per
+// mode in the [Filters] section of pikeru.conf with the same word syntax as the
+// portal's [postprocess] rules, like `files = kind:image,video max_size:20M max_age:30`.
+use std::fmt;
+use crate::policy::parse_size;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Dir,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 5] = [Kind::Image, Kind::Video, Kind::Audio, Kind::Document, Kind::Dir];
+
+    /// Guesses the kind from the extension, so items can be filtered before they're loaded
+    pub fn of(path: &str, isdir: bool) -> Option<Self> {
+        if isdir {
+            return Some(Kind::Dir);
+        }
+        let ext = path.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+        match ext.as_str() {
+            "png"|"jpg"|"jpeg"|"bmp"|"tiff"|"gif"|"webp"|"svg"|"avif"|"jxl"|"heic" => Some(Kind::Image),
+            "webm"|"mkv"|"mp4"|"av1"|"avi"|"flv"|"wmv"|"m4v"|"mpeg"|"mov" => Some(Kind::Video),
+            "mp3"|"wav"|"ogg"|"flac"|"aac"|"wma"|"aiff"|"alac"|"opus"|"m4a" => Some(Kind::Audio),
+            "txt"|"md"|"pdf"|"doc"|"docx"|"odt"|"xls"|"xlsx"|"ods"|"ppt"|"pptx"|"odp"|"rtf"|"epub"|"csv" => Some(Kind::Document),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Kind::Image => "Images",
+            Kind::Video => "Videos",
+            Kind::Audio => "Audio",
+            Kind::Document => "Documents",
+            Kind::Dir => "Directories",
+        }
+    }
+
+    pub fn of_name(name: &str) -> Option<Self> {
+        Kind::ALL.into_iter().find(|k| k.name() == name)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Image => "image",
+            Kind::Video => "video",
+            Kind::Audio => "audio",
+            Kind::Document => "document",
+            Kind::Dir => "dir",
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Filter {
+    /// shown kinds, all of them when empty
+    pub kinds: Vec<Kind>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// days since the last modification
+    pub max_age: Option<f32>,
+}
+
+impl Filter {
+    pub fn parse(txt: &str) -> Result<Self, String> {
+        let mut filter = Filter::default();
+        for word in txt.split_whitespace() {
+            match word.split_once(':') {
+                Some(("kind", v)) => for kind in v.split(',').filter(|k| !k.is_empty()) {
+                    let kind = Kind::of_name(kind).ok_or(format!("unknown kind {}", kind))?;
+                    filter.kinds.push(kind);
+                },
+                Some(("min_size", v)) => filter.min_size = Some(parse_size(v).ok_or(format!("bad min_size {}", v))?),
+                Some(("max_size", v)) => filter.max_size = Some(parse_size(v).ok_or(format!("bad max_size {}", v))?),
+                Some(("max_age", v)) => filter.max_age = Some(v.parse().map_err(|_| format!("bad max_age {}", v))?),
+                _ => return Err(format!("unknown filter {}", word)),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Filter::default()
+    }
+
+    /// Limits only apply to files, so directories stay navigable whatever kinds are picked.
+    /// A filter of just `kind:dir` hides every file.
+    pub fn allows(&self, path: &str, isdir: bool, size: u64, mtime: f32, now: f32) -> bool {
+        if isdir {
+            return true;
+        }
+        (self.kinds.is_empty() || Kind::of(path, isdir).is_some_and(|k| self.kinds.contains(&k)))
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self.max_age.is_none_or(|days| now - mtime <= days * 86400.0)
+    }
+
+    /// Min size, max size and max age as the filter menu shows them
+    pub fn menu_text(&self) -> [String; 3] {
+        [
+            self.min_size.map(format_size).unwrap_or_default(),
+            self.max_size.map(format_size).unwrap_or_default(),
+            self.max_age.map(|days|days.to_string()).unwrap_or_default(),
+        ]
+    }
+
+    pub fn toggle(&mut self, kind: Kind, on: bool) {
+        self.kinds.retain(|k| *k != kind);
+        if on {
+            self.kinds.push(kind);
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut words = vec![];
+        if !self.kinds.is_empty() {
+            words.push(format!("kind:{}", self.kinds.iter().map(Kind::name).collect::<Vec<_>>().join(",")));
+        }
+        if let Some(min) = self.min_size {
+            words.push(format!("min_size:{}", format_size(min)));
+        }
+        if let Some(max) = self.max_size {
+            words.push(format!("max_size:{}", format_size(max)));
+        }
+        if let Some(days) = self.max_age {
+            words.push(format!("max_age:{}", days));
+        }
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// Writes bytes with the largest K, M or G suffix that divides them evenly
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0 => "0".to_string(),
+        b if b % (1 << 30) == 0 => format!("{}G", b >> 30),
+        b if b % (1 << 20) == 0 => format!("{}M", b >> 20),
+        b if b % (1 << 10) == 0 => format!("{}K", b >> 10),
+        b => b.to_string(),
+    }
+}
@@ -13,6 +13,20 @@ mod iced_drop;
 mod mouse;
 use mouse::mouse_area;
 mod style;
+mod policy;
+use policy::Policy;
+mod exif;
+mod uri;
+mod rawpath;
+mod filter;
+use filter::{Filter, Kind};
+mod query;
+use query::{Query, Hit};
+mod content;
+mod walk;
+use walk::WalkOpts;
+mod saved;
+use saved::SavedSearch;
 use iced::{
     advanced::widget::Id,
     Rectangle,
@@ -23,7 +37,7 @@ use iced::{
     mouse::ScrollDelta,
     keyboard::Event::{KeyPressed,KeyReleased},
     keyboard::Key,
-    keyboard::key::Named::{Shift,Control,ArrowUp,ArrowDown,ArrowLeft,ArrowRight,Enter,Backspace,PageUp,PageDown},
+    keyboard::key::Named::{Shift,Control,ArrowUp,ArrowDown,ArrowLeft,ArrowRight,Enter,Backspace,PageUp,PageDown,Home,End,Escape},
     keyboard::key::Named,
     widget::{
         horizontal_space, vertical_space, checkbox, slider,
@@ -32,7 +46,7 @@ use iced::{
         Scrollable, scrollable, scrollable::{Direction,Properties},
         Button, TextInput, Text,
         column, row, container,
-        svg
+        svg, tooltip
     },
     futures::{
         sink::SinkExt,
@@ -55,9 +69,10 @@ use std::{
     fs, str, mem,
     path::{PathBuf,Path},
     process::{self, Command as OsCmd},
-    sync::Arc,
+    sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}},
     time::{Instant,Duration},
     cell::RefCell,
+    os::unix::ffi::OsStrExt,
 };
 use video_rs::{Decoder, Location, DecoderBuilder, Resize};
 use ndarray;
@@ -69,18 +84,33 @@ use iced_aw::{
     modal, Card
 };
 use md5::{Md5,Digest};
-use fuzzy_matcher::{self, FuzzyMatcher};
 use zbus::{Result,proxy,Connection,blocking};
-use ignore::{gitignore,Match};
+use ignore::WalkState;
+
+/// Exit status for errors. Cancelling exits with 0, or 1 with --exit-code.
+const ERROR_EXIT: i32 = 2;
+/// sort_by values from 1 by their names in pikeru.conf
+const SORTS: [&str; 4] = ["name_asc", "name_desc", "age_asc", "age_desc"];
+/// Search results added to the grid at a time, more are shown when scrolling near the end
+const SEARCH_PAGE: usize = 1000;
 
 macro_rules! die {
     ($($arg:tt)*) => {{
         eprintln!($($arg)*);
-        std::process::exit(1);
+        std::process::exit(ERROR_EXIT);
     }};
 }
 
 fn cli(flags: &getopts::Matches) {
+    if flags.free.first().is_some_and(|c| c == "history") {
+        let sql = IndexProxy::open_db().unwrap_or_else(||die!("Could not open index.db"));
+        for (time, app_id, mode, paths, postprocessed) in IndexProxy::get_history(&sql, flags.free.get(1).map(|s|s.as_str())) {
+            println!("{} {} {}{}", time, if app_id.is_empty() { "(unknown app)" } else { app_id.as_str() }, mode,
+                     if postprocessed { " postprocessed" } else { "" });
+            paths.lines().for_each(|path| { print!("    "); print_path(path, "\n") });
+        }
+        std::process::exit(0);
+    }
     if flags.opt_present("c") {
         IndexProxy::pause_resume(false);
         std::process::exit(0);
@@ -111,7 +141,85 @@ fn main() -> iced::Result {
     let mut conf = Config::new();
     conf.update(false);
     video_rs::init().unwrap();
-    FilePicker::run(iced::Settings::with_flags(conf))
+    let (output, exit_code) = (conf.output, conf.exit_code);
+    let filter = conf.filter().cloned();
+    FilePicker::run(iced::Settings::with_flags(conf))?;
+    // the window was closed without selecting anything
+    cancel(output, filter.as_ref(), exit_code)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Output {
+    Lines,
+    Null,
+    Uri,
+    Json,
+}
+
+/// Prints the selection in the format asked for with --output
+fn print_selection(output: Output, filter: Option<&Filter>, paths: &[String]) {
+    match output {
+        Output::Lines => paths.iter().for_each(|path| print_path(path, "\n")),
+        Output::Null => paths.iter().for_each(|path| print_path(path, "\0")),
+        Output::Uri => paths.iter().for_each(|path| println!("{}", uri::file_uri(&rawpath::to_path(path)))),
+        Output::Json => {
+            let files = paths.iter().map(|path| {
+                let path = rawpath::to_path(path);
+                let md = std::fs::metadata(&path);
+                let ftype = match &md {
+                    Ok(md) if md.is_dir() => "dir",
+                    Ok(_) => "file",
+                    Err(_) => "new",
+                };
+                let (size, mtime) = match md {
+                    Ok(md) => (md.len().to_string(), md.modified().ok()
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map_or("null".to_string(), |d| d.as_secs().to_string())),
+                    Err(_) => ("null".to_string(), "null".to_string()),
+                };
+                format!("{{\"path\":{},\"type\":\"{}\",\"size\":{},\"mtime\":{}}}", json_str(&path.to_string_lossy()), ftype, size, mtime)
+            }).join(",");
+            println!("{{\"cancelled\":false,\"filter\":{},\"files\":[{}]}}", json_filter(filter), files);
+        },
+    }
+}
+
+/// Prints a path with the bytes it has on disk, which aren't always valid UTF-8
+fn print_path(path: &str, end: &str) {
+    let mut out = std::io::stdout().lock();
+    let _ = std::io::Write::write_all(&mut out, &rawpath::to_bytes(path))
+        .and_then(|_| std::io::Write::write_all(&mut out, end.as_bytes()));
+}
+
+/// Prints an empty selection and exits, with 1 if --exit-code was given
+fn cancel(output: Output, filter: Option<&Filter>, exit_code: bool) -> ! {
+    if output == Output::Json {
+        println!("{{\"cancelled\":true,\"filter\":{},\"files\":[]}}", json_filter(filter));
+    }
+    process::exit(if exit_code { 1 } else { 0 });
+}
+
+/// The filter in effect in its pikeru.conf form, or null without one
+fn json_filter(filter: Option<&Filter>) -> String {
+    filter.filter(|f|!f.is_empty()).map_or("null".to_string(), |f|json_str(&f.to_string()))
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
 struct Config {
@@ -127,6 +235,23 @@ struct Config {
     need_update: bool,
     gitignore: String,
     respect_gitignore: bool,
+    /// levels of subdirectories recursive searches go into, all of them when None
+    search_depth: Option<usize>,
+    follow_symlinks: bool,
+    cross_filesystems: bool,
+    write_xattrs: bool,
+    recent_uploads: bool,
+    policy: Option<Arc<Policy>>,
+    output: Output,
+    exit_code: bool,
+    stdin_list: Option<Vec<String>>,
+    view: Option<String>,
+    /// filters by mode name
+    filters: HashMap<String, Filter>,
+    /// sort and filter of a loaded saved search, used instead of the mode's until
+    /// another dir is loaded so they never end up in pikeru.conf
+    saved_view: Option<(i32, Filter)>,
+    saved_searches: Vec<SavedSearch>,
     home: String,
 }
 
@@ -135,29 +260,90 @@ impl Config {
     #[inline]
     fn saving(self: &Self) -> bool { self.mode == Mode::Save }
     #[inline]
-    fn multi(self: &Self) -> bool { self.mode == Mode::Files }
+    fn multi(self: &Self) -> bool { matches!(self.mode, Mode::Files | Mode::Dirs | Mode::Any) }
+    /// directories can be returned, not just opened
+    #[inline]
+    fn dir(self: &Self) -> bool { matches!(self.mode, Mode::Dir | Mode::Dirs | Mode::Any) }
+    /// files and directories can be selected together
+    #[inline]
+    fn mixed(&self) -> bool { self.mode == Mode::Any }
+    #[inline]
+    fn can_browse(&self, path: &str) -> bool { self.policy.as_ref().is_none_or(|p| p.allows(&rawpath::to_path(path), true)) }
     #[inline]
-    fn dir(self: &Self) -> bool { self.mode == Mode::Dir }
+    fn can_select(&self, path: &str) -> bool { self.policy.as_ref().is_none_or(|p| p.allows(&rawpath::to_path(path), false)) }
+    /// choosing from paths given on stdin instead of browsing
+    #[inline]
+    fn listing(&self) -> bool { self.stdin_list.is_some() }
+    #[inline]
+    fn filter(&self) -> Option<&Filter> {
+        match &self.saved_view {
+            Some((_, filter)) => Some(filter),
+            None => self.filters.get(self.mode.name()),
+        }
+    }
+    fn filter_mut(&mut self) -> &mut Filter {
+        if let Some((_, filter)) = &mut self.saved_view {
+            return filter;
+        }
+        self.need_update = true;
+        self.filters.entry(self.mode.name().to_string()).or_default()
+    }
+    #[inline]
+    fn sort_by(&self) -> i32 { self.saved_view.as_ref().map_or(self.sort_by, |(sort_by, _)| *sort_by) }
+    fn set_sort_by(&mut self, sort_by: i32) {
+        match &mut self.saved_view {
+            Some((view_sort, _)) => *view_sort = sort_by,
+            None => {
+                self.need_update |= sort_by != self.sort_by;
+                self.sort_by = sort_by;
+            },
+        }
+    }
+    fn walk_opts(&self) -> WalkOpts {
+        WalkOpts {
+            gitignore: self.gitignore.clone(),
+            respect_gitignore: self.respect_gitignore,
+            max_depth: self.search_depth,
+            follow_symlinks: self.follow_symlinks,
+            cross_filesystems: self.cross_filesystems,
+            policy: self.policy.clone(),
+        }
+    }
 
     fn new() -> Self {
-        let args: Vec<String> = std::env::args().skip(1).collect();
+        // paths from the portal don't have to be UTF-8, and std::env::args panics on those
+        let mut args: Vec<String> = std::env::args_os().skip(1).map(rawpath::to_str).collect();
+        // in 'pikeru search DIR... -- QUERY' the query can start with a dash
+        let search_query = if args.first().is_some_and(|a| a == "search") {
+            args.iter().position(|a| a == "--").map(|i| args.split_off(i)[1..].join(" "))
+        } else { None };
         let mut opts = Options::new();
         let pwd = std::env::var("PWD").unwrap();
         opts.optopt("t", "title", "Title of the filepicker window", "NAME");
-        opts.optopt("m", "mode", "Mode of file selection. Default is files", "[file, files, save, dir]");
+        opts.optopt("m", "mode", "Mode of file selection. Default is files", "[file, files, save, dir, dirs, any]");
         opts.optopt("p", "path", "Initial path", "PATH");
         opts.optflag("c", "pause", "Pause the semantic search indexer");
         opts.optflag("b", "resume", "Resume the semantic search indexer");
         opts.optflag("d", "disable", "Configure xdg portal to not use pikeru as your system filepicker");
         opts.optflag("e", "enable", "Configure xdg portal to use pikeru as your system filepicker");
         opts.optflag("h", "help", "Show usage information");
+        opts.optmulti("", "allow", "Only show files under this path. Can be repeated", "PATH");
+        opts.optmulti("", "deny", "Hide files matching this gitignore pattern. Can be repeated", "GLOB");
+        opts.optopt("", "max-size", "Hide files larger than this", "BYTES");
+        opts.optopt("", "output", "How to print selected paths. null separates them with NUL bytes and json adds type, size and mtime. Default is lines", "[lines, null, uri, json]");
+        opts.optflag("", "exit-code", "Exit with 1 when cancelled. Errors always exit with 2");
+        opts.optflag("", "stdin", "Choose from NUL or newline separated paths read from stdin instead of browsing");
+        opts.optflag("", "content", "With search, match text inside files and print the first matching line of each");
         let matches = match opts.parse(&args) {
             Ok(m) => m,
             Err(e) => die!("Bad args: {}", e),
         };
         if matches.opt_present("h") {
-            println!("{}\n{}",opts.usage(&args[0]),
-                "File picker config file is ~/.config/pikeru.conf.\nThe portal config file, which includes the semantic search indexer and postprocessor, is by default ~/.config/xdg-desktop-portal-pikeru/config");
+            println!("{}\nRun 'pikeru history [APP_ID]' to see which files the portal gave to which applications.
+Run 'pikeru view FILE|DIR' to open an image, or the first image in a directory, in the image viewer.
+Run 'pikeru search [DIR]... -- QUERY' to print files matching QUERY with their scores, best first. --output json adds descriptions.
+Run 'pikeru search --content [DIR]... -- TEXT' to print the first line containing TEXT in each text file, like grep -n.
+File picker config file is ~/.config/pikeru.conf.\nThe portal config file, which includes the semantic search indexer and postprocessor, is by default ~/.config/xdg-desktop-portal-pikeru/config", opts.usage(&args[0]));
             std::process::exit(0);
         }
 
@@ -165,23 +351,32 @@ impl Config {
         let confpath = Path::new(&home).join(".config").join("pikeru.conf").to_string_lossy().to_string();
         let txt = std::fs::read_to_string(confpath).unwrap_or("".to_string());
         #[derive(PartialEq)]
-        enum S { Commands, Settings, Bookmarks, Ignore }
+        enum S { Commands, Settings, Bookmarks, Ignore, Filters, Saved }
         let mut section = S::Commands;
         let mut bookmarks = vec![];
         let mut cmds = vec![];
+        let mut filters = HashMap::new();
+        let mut saved_searches = vec![];
         let mut respect_gitignore = true;
+        let mut search_depth = None;
+        let mut follow_symlinks = true;
+        let mut cross_filesystems = true;
+        let mut write_xattrs = false;
+        let mut recent_uploads = false;
         let mut gitignore = ".git/\n".to_string();
         let mut sort_by = 1;
         let mut thumb_size = 160.0;
         let mut window_size: Size = Size { width: 1024.0, height: 768.0 };
         let mut dpi_scale: f32 = 1.0;
-        let mut opts_missing = 5;
+        let mut opts_missing = 10;
         for line in txt.lines().map(|s|s.trim()).filter(|s|s.len()>0 && !s.starts_with('#')) {
             match line {
                 "[Commands]" => section = S::Commands,
                 "[Settings]" => section = S::Settings,
                 "[Bookmarks]" => section = S::Bookmarks,
                 "[SearchIgnore]" => { section = S::Ignore; gitignore.clear(); },
+                "[Filters]" => section = S::Filters,
+                "[SavedSearches]" => section = S::Saved,
                 _ => {
                     let (k, v) = str::split_once(line, '=').unwrap_or(("",""));
                     let (k, v) = (k.trim(), v.trim());
@@ -189,10 +384,23 @@ impl Config {
                         S::Commands => cmds.push(Cmd::new(k, v)),
                         S::Bookmarks => bookmarks.push(Bookmark::new(k,v)),
                         S::Ignore => {gitignore += line; gitignore += "\n"; },
+                        S::Filters => match Filter::parse(v) {
+                            Ok(filter) => { filters.insert(k.to_string(), filter); },
+                            Err(e) => eprintln!("Bad filter for {} mode: {}", k, e),
+                        },
+                        S::Saved => match SavedSearch::parse(k, v) {
+                            Ok(saved) => saved_searches.push(saved),
+                            Err(e) => eprintln!("Bad saved search {}: {}", k, e),
+                        },
                         S::Settings => match k {
                             "thumbnail_size" => { opts_missing -= 1; thumb_size = v.parse().unwrap() },
                             "dpi_scale" => { opts_missing -= 1; dpi_scale = v.parse().unwrap() },
                             "respect_gitignore" => { opts_missing -= 1; respect_gitignore = v.parse().unwrap() },
+                            "search_depth" => { opts_missing -= 1; search_depth = Some(v.parse().unwrap()).filter(|d|*d > 0) },
+                            "follow_symlinks" => { opts_missing -= 1; follow_symlinks = v.parse().unwrap() },
+                            "cross_filesystems" => { opts_missing -= 1; cross_filesystems = v.parse().unwrap() },
+                            "write_xattrs" => { opts_missing -= 1; write_xattrs = v.parse().unwrap() },
+                            "recent_uploads" => { opts_missing -= 1; recent_uploads = v.parse().unwrap() },
                             "window_size" => {
                                 opts_missing -= 1;
                                 if !match str::split_once(v, 'x') {
@@ -207,12 +415,7 @@ impl Config {
                             }
                             "sort_by" => {
                                 opts_missing -= 1;
-                                sort_by = match v {
-                                    "name_desc" => 2,
-                                    "age_asc" => 3,
-                                    "age_desc" => 4,
-                                    _ => 1,
-                                }
+                                sort_by = SORTS.iter().position(|s| *s == v).map_or(1, |i| i as i32 + 1);
                             },
                             _ => {},
                         },
@@ -231,10 +434,36 @@ impl Config {
             bookmarks.push(Bookmark::new("Documents", Path::new(&home).join("Documents").to_string_lossy().as_ref()));
             bookmarks.push(Bookmark::new("Pictures", Path::new(&home).join("Pictures").to_string_lossy().as_ref()));
         }
-        Config {
+        let (allow, deny) = (matches.opt_strs("allow"), matches.opt_strs("deny"));
+        let max_size = matches.opt_str("max-size").map(|m| m.parse::<u64>().unwrap_or_else(|e| die!("Bad max-size: {}", e)));
+        let policy = if allow.is_empty() && deny.is_empty() && max_size.is_none() { None } else {
+            let roots = allow.iter().map(|root| rawpath::to_path(root).into_owned()).collect::<Vec<_>>();
+            Some(Arc::new(Policy::new(&roots, &deny, max_size)))
+        };
+        let output = match matches.opt_str("output").as_deref() {
+            None | Some("lines") => Output::Lines,
+            Some("null") => Output::Null,
+            Some("uri") => Output::Uri,
+            Some("json") => Output::Json,
+            Some(o) => die!("Unknown output format: {}", o),
+        };
+        let view = matches.free.first().is_some_and(|c| c == "view").then(||
+            Path::new(&pwd).join(matches.free.get(1).unwrap_or(&pwd)).to_string_lossy().to_string());
+        let stdin_list = matches.opt_present("stdin").then(|| {
+            let mut buf = vec![];
+            if let Err(e) = std::io::Read::read_to_end(&mut std::io::stdin(), &mut buf) {
+                die!("Error reading stdin: {}", e);
+            }
+            let sep = if buf.contains(&0) { b'\0' } else { b'\n' };
+            buf.split(|&b| b == sep).filter(|p| !p.is_empty()).map(|p| {
+                let p = p.strip_suffix(b"\r").unwrap_or(p);
+                rawpath::to_str(rawpath::to_path(&pwd).join(std::ffi::OsStr::from_bytes(p)))
+            }).unique().collect::<Vec<_>>()
+        });
+        let conf = Config {
             mode: Mode::from(matches.opt_str("m")),
-            path: matches.opt_str("p").unwrap_or(pwd),
-            title: matches.opt_str("t").unwrap_or("File Picker".to_string()),
+            path: view.clone().or(matches.opt_str("p")).unwrap_or(pwd.clone()),
+            title: matches.opt_str("t").unwrap_or(if view.is_some() { "Image Viewer" } else { "File Picker" }.to_string()),
             cmds,
             bookmarks,
             sort_by,
@@ -243,9 +472,36 @@ impl Config {
             dpi_scale: dpi_scale.into(),
             gitignore,
             respect_gitignore,
+            search_depth,
+            follow_symlinks,
+            cross_filesystems,
+            write_xattrs,
+            recent_uploads,
+            policy,
+            output,
+            exit_code: matches.opt_present("exit-code"),
+            stdin_list,
+            view,
+            filters,
+            saved_view: None,
+            saved_searches,
             need_update: opts_missing > 0,
             home,
+        };
+        if matches.free.first().is_some_and(|c| c == "search") {
+            let mut dirs = matches.free[1..].to_vec();
+            let query = search_query.or_else(|| dirs.pop()).unwrap_or_else(|| die!("Usage: pikeru search [DIR]... -- QUERY"));
+            if dirs.is_empty() {
+                dirs.push(pwd.clone());
+            }
+            let dirs: Vec<String> = dirs.iter().map(|d| Path::new(&pwd).join(d).components().collect::<PathBuf>()
+                                                     .to_string_lossy().to_string()).collect();
+            if matches.opt_present("content") {
+                grep(&conf, &dirs, &query);
+            }
+            search(&conf, &dirs, &query);
         }
+        conf
     }
 
     fn update(self: &mut Config, force: bool) {
@@ -268,17 +524,36 @@ impl Config {
             conf.push_str(&cmd.cmd);
             conf.push('\n');
         });
-        conf.push_str("\n[Settings]\n");
+        conf.push_str("\n# write_xattrs also saves tags from the tag editor to user.xdg.tags and user.xdg.comment
+# recent_uploads adds a bookmark listing files the portal recently gave to applications
+# search_depth limits how many levels of subdirectories a recursive search goes into, 0 for no limit\n[Settings]\n");
         conf.push_str(format!(
-                "dpi_scale = {}\nwindow_size = {}x{}\nthumbnail_size = {}\nsort_by = {}\nrespect_gitignore = {}\n",
+                "dpi_scale = {}\nwindow_size = {}x{}\nthumbnail_size = {}\nsort_by = {}\nrespect_gitignore = {}\nsearch_depth = {}\nfollow_symlinks = {}\ncross_filesystems = {}\nwrite_xattrs = {}\nrecent_uploads = {}\n",
                 self.dpi_scale,
                 self.window_size.width as i32, self.window_size.height as i32,
                 self.thumb_size as i32,
-                match self.sort_by { 1=>"name_asc", 2=>"name_desc", 3=>"age_asc", 4=>"age_desc", _=>"" },
-                self.respect_gitignore).as_str());
+                SORTS[self.sort_by as usize - 1],
+                self.respect_gitignore,
+                self.search_depth.unwrap_or(0),
+                self.follow_symlinks,
+                self.cross_filesystems,
+                self.write_xattrs,
+                self.recent_uploads).as_str());
         conf.push_str("\n# The SearchIgnore section uses gitignore syntax rather than ini.
 # The respect_gitignore setting only toggles .gitignore files, not this section.\n[SearchIgnore]\n");
         conf.push_str(self.gitignore.as_str());
+        conf.push_str("\n# Filters set from the Filter menu for each picker mode, like
+# files = kind:image,video,audio,document,dir min_size:100K max_size:20M max_age:30
+[Filters]\n");
+        self.filters.iter().filter(|(_, filter)|!filter.is_empty()).sorted_by_key(|(mode, _)|mode.as_str()).for_each(|(mode, filter)| {
+            conf.push_str(&format!("{} = {}\n", mode, filter));
+        });
+        conf.push_str("\n# Searches saved from the search bar, listed under the bookmarks, with the format
+# name = dir:dir | recursive content sort:age_desc and filter words | search text
+[SavedSearches]\n");
+        self.saved_searches.iter().for_each(|saved| {
+            conf.push_str(&format!("{} = {}\n", saved.label, saved));
+        });
         conf.push_str("\n[Bookmarks]\n");
         self.bookmarks.iter().for_each(|bm| {
             conf.push_str(&bm.label);
@@ -301,6 +576,8 @@ enum Mode {
     Files,
     Save,
     Dir,
+    Dirs,
+    Any,
 }
 impl Mode {
    fn from(opt: Option<String>) -> Self {
@@ -312,28 +589,55 @@ impl Mode {
                    "files" => Self::Files,
                    "save" => Self::Save,
                    "dir" => Self::Dir,
+                   "dirs" => Self::Dirs,
+                   "any" => Self::Any,
                    _ => Self::Files,
                }
            }
        }
    }
+
+   /// The -m value for this mode, which also keys its saved filter
+   fn name(&self) -> &'static str {
+       match self {
+           Self::File => "file",
+           Self::Files => "files",
+           Self::Save => "save",
+           Self::Dir => "dir",
+           Self::Dirs => "dirs",
+           Self::Any => "any",
+       }
+   }
 }
 
 #[derive(Clone, Debug)]
 enum SearchEvent {
-    NewItems(Vec<String>, u8),
-    AddItems(Vec<String>),
+    NewItems(Vec<FileIdx<'static>>, u8),
+    AddItems(Vec<FileIdx<'static>>),
+    SetAttrs(Vec<(usize, Option<String>)>),
     NewView(Vec<usize>),
     AddView(Vec<usize>),
-    AddSemantics(Vec<(String,String)>),
+    AddSemantics(Vec<(String,String,String)>),
+    AddPalettes(Vec<(String, Vec<[u8; 3]>)>),
+    /// items indexes and scores of the matches, best first, with how many items were searched
     Results(Vec<(usize, i64)>, u8, usize, String),
+    /// matches among items added since the last search for the same term, to merge into its results
+    MoreResults(Vec<(usize, i64)>, u8, usize, String),
+    /// asks where these items matched the term, for the ones being shown
+    FindHits(Vec<usize>, String),
+    Hits(Vec<(usize, Hit)>, u8, String),
+    /// (path, line number, snippet) from a content search, with its nav id and term
+    ContentMatches(Vec<(String, usize, String)>, u8, String),
     Search(String),
+    /// the same term again after more items came in, only searching those when nothing else changed
+    SearchMore(String),
 }
 
 #[derive(Debug, Clone)]
 enum Message {
     LoadDir,
     LoadBookmark(usize),
+    LoadRecent,
     Goto,
     Select(SelType),
     OverWriteOK,
@@ -342,6 +646,21 @@ enum Message {
     DownDir,
     NewDir(bool),
     NewDirInput(String),
+    LoadSavedSearch(usize),
+    SaveSearch(bool),
+    SaveSearchInput(String),
+    EditTags,
+    TagsInput(String),
+    UserDescInput(String),
+    SaveTags,
+    TagsSaved(SavedTags, u8),
+    FindSimilar,
+    FindDuplicates,
+    /// hash distances from the picked image, or None with its path when it can't be hashed
+    Similar(Option<HashMap<usize, u32>>, String, u8),
+    /// items in groups that look alike
+    Duplicates(Vec<usize>, u8),
+    Location(Vec<(usize, bool)>, u8),
     Init((USender<FItem>, USender<Inochan>, USender<SearchEvent>, USender<RecMsg>)),
     NextItem(FItem),
     LoadThumbs,
@@ -362,15 +681,25 @@ enum Message {
     ArrowKey(Named),
     ShowHidden(bool),
     SetRecursive(bool),
+    /// dirs scanned, whether the walk ended, nav id
+    RecurseProgress(usize, bool, u8),
+    StopRecurse,
+    SetContentSearch(bool),
+    ShowAllResults,
     RunCmd(usize),
     InoDelete(String),
     InoCreate(String),
     Thumbsize(f32),
+    FilterKind(Kind, bool),
+    /// min size, max size or max age text from the filter menu
+    FilterInput(usize, String),
+    ClearFilter,
     CloseModal,
     SearchResult(Box<SearchEvent>),
     NextRecurse(Vec<FItem>, u8),
     PageUp,
     PageDown,
+    CloseView,
     Dummy,
 }
 
@@ -415,6 +744,11 @@ struct FItemb {
     hidden: bool,
     recursed: bool,
     unicode: bool,
+    xdg_tags: Option<String>,
+    xdg_comment: Option<String>,
+    phash: Option<u64>,
+    palette: Vec<[u8; 3]>,
+    gps: Option<bool>,
 }
 #[derive(Debug, Clone, Default)]
 struct FItem(Box<FItemb>);
@@ -458,6 +792,8 @@ struct Cmd {
 enum FModal {
     None,
     NewDir,
+    SaveSearch,
+    Tags(usize),
     OverWrite,
     Error(String),
 }
@@ -476,12 +812,16 @@ struct LastClicked {
     new: bool,
     nav_id: u8,
     size: Option<String>,
+    location: bool,
 }
 
+/// How far the recursive search walk of the current dirs got
 #[derive(PartialEq)]
 enum RecState {
+    Idle,
     Run,
-    Stop,
+    Done,
+    Stopped,
 }
 
 #[proxy(
@@ -494,6 +834,8 @@ trait Indexer {
     async fn pause_resume(&self, active: bool) -> Result<()>;
     async fn configure(&mut self, respect_gitignore: bool, ignore: &str) -> Result<()>;
 }
+/// (path, description, tags) and (path, palette) of indexed files
+type Semantics = (Vec<(String,String,String)>, Vec<(String, Vec<[u8; 3]>)>);
 struct IndexProxy<'a> {
     proxy: Option<IndexerProxy<'a>>,
     done: HashSet<String>,
@@ -507,17 +849,108 @@ impl<'a> IndexProxy<'a> {
             let prox = IndexerProxy::new(&conn).await.ok()?;
             Some(prox)
         }.await;
-        let home = std::env::var("HOME").unwrap();
-        let idxfile = Path::new(&home).join(".cache").join("pikeru").join("index.db");
-        let sql = match rusqlite::Connection::open(&idxfile) {
-            Ok(con) => Some(con),
-            Err(_) => None,
-        };
         Self {
             proxy,
             done: HashSet::new(),
-            sql,
+            sql: Self::open_db(),
+        }
+    }
+
+    /// Opens index.db, creating the descriptions table with the user-authored columns if needed.
+    fn open_db() -> Option<rusqlite::Connection> {
+        let home = std::env::var("HOME").unwrap();
+        let idxfile = Path::new(&home).join(".cache").join("pikeru").join("index.db");
+        let con = rusqlite::Connection::open(&idxfile).ok()?;
+        if let Err(e) = con.execute("create table if not exists descriptions
+                      (fname text, dir text, description text, mtime real,
+                      tags text, user_description text);", ()) {
+            eprintln!("Error creating index table: {}", e);
         }
+        for col in ["tags", "user_description"] {
+            let _ = con.execute(format!("alter table descriptions add column {} text", col).as_str(), ());
+        }
+        // perceptual hashes are computed from thumbnails, so the picker owns this table
+        if let Err(e) = con.execute("create table if not exists thumbinfo
+                      (dir text, fname text, mtime real, phash integer, palette text, primary key (dir, fname));", ()) {
+            eprintln!("Error creating thumbinfo table: {}", e);
+        }
+        let _ = con.execute("alter table thumbinfo add column palette text", ());
+        let _ = con.pragma_update(None, "synchronous", "NORMAL");
+        Some(con)
+    }
+
+    fn save_thumbinfo(sql: &rusqlite::Connection, path: &str, mtime: f32, info: &ThumbInfo) {
+        let p = Path::new(path);
+        let dir = p.parent().unwrap_or(Path::new("")).to_string_lossy();
+        let fname = p.file_name().unwrap_or_default().to_string_lossy();
+        let palette = info.palette.iter().map(|c|format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])).join(",");
+        if let Err(e) = sql.execute("insert or replace into thumbinfo (dir, fname, mtime, phash, palette) values (?1, ?2, ?3, ?4, ?5)",
+                                    (dir.as_ref(), fname.as_ref(), mtime, info.phash.map(|h|h as i64), palette)) {
+            eprintln!("Error saving image info for {}: {}", path, e);
+        }
+    }
+
+    /// Returns stored hashes by file name for a directory, with the mtime they were computed at
+    fn get_hashes(sql: &rusqlite::Connection, dir: &str) -> HashMap<String, (f32, u64)> {
+        let mut query = match sql.prepare("select fname, mtime, phash from thumbinfo where dir = ?1 and phash is not null") {
+            Ok(q) => q,
+            Err(_) => return HashMap::new(),
+        };
+        let rows = query.query_map([dir], |row| Ok((row.get::<_,String>(0)?, (row.get::<_,f32>(1)?, row.get::<_,i64>(2)? as u64))));
+        match rows {
+            Ok(rows) => rows.filter_map(|r|r.ok()).collect(),
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    fn get_user_text(sql: &rusqlite::Connection, path: &str) -> (String, String) {
+        let p = Path::new(path);
+        let dir = p.parent().unwrap_or(Path::new("")).to_string_lossy();
+        let fname = p.file_name().unwrap_or_default().to_string_lossy();
+        sql.query_row("select coalesce(tags, ''), coalesce(user_description, '') from descriptions
+                      where dir = ?1 and fname = ?2", [dir.as_ref(), fname.as_ref()],
+                      |row| Ok((row.get(0)?, row.get(1)?))).unwrap_or_default()
+    }
+
+    /// Saves user tags and description, which the indexer never overwrites.
+    /// Returns the new searchable (path, description, tags) for the search loop.
+    fn set_user_text(sql: &rusqlite::Connection, path: &str, tags: &str, desc: &str) -> rusqlite::Result<(String,String,String)> {
+        let p = Path::new(path);
+        let dir = p.parent().unwrap_or(Path::new("")).to_string_lossy();
+        let fname = p.file_name().unwrap_or_default().to_string_lossy();
+        let tags = if tags.trim().is_empty() { None } else { Some(tags.trim()) };
+        let desc = if desc.trim().is_empty() { None } else { Some(desc.trim()) };
+        let changed = sql.execute("update descriptions set tags = ?3, user_description = ?4 where dir = ?1 and fname = ?2",
+                                  (dir.as_ref(), fname.as_ref(), tags, desc))?;
+        if changed == 0 {
+            sql.execute("insert into descriptions (dir, fname, tags, user_description) values (?1, ?2, ?3, ?4)",
+                        (dir.as_ref(), fname.as_ref(), tags, desc))?;
+        }
+        sql.query_row("select coalesce(user_description, description, ''), coalesce(tags, '') from descriptions
+                      where dir = ?1 and fname = ?2", [dir.as_ref(), fname.as_ref()],
+                      |row| Ok((path.to_string(), row.get(0)?, row.get(1)?)))
+    }
+
+    /// Returns file requests logged by the portal, newest first, as
+    /// (local time, app id, mode, newline separated paths, postprocessed)
+    fn get_history(sql: &rusqlite::Connection, app_id: Option<&str>) -> Vec<(String, String, String, String, bool)> {
+        let mut query = match sql.prepare("select datetime(time, 'unixepoch', 'localtime'), coalesce(app_id, ''), mode, paths, postprocessed
+                                          from history where ?1 is null or app_id = ?1 order by time desc") {
+            Ok(q) => q,
+            Err(_) => return vec![],
+        };
+        let rows = query.query_map([app_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)));
+        match rows {
+            Ok(rows) => rows.filter_map(|r|r.ok()).collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Files recently opened through the portal that still exist
+    fn get_uploads(sql: &rusqlite::Connection) -> Vec<String> {
+        Self::get_history(sql, None).into_iter().filter(|h|h.2 != "save")
+            .flat_map(|h|h.3.lines().map(str::to_string).collect::<Vec<_>>())
+            .unique().filter(|p|rawpath::to_path(p).exists()).collect()
     }
 
     fn pause_resume(active: bool) {
@@ -542,21 +975,10 @@ impl<'a> IndexProxy<'a> {
         } 
     }
 
-    async fn update(&mut self, dirs: &Vec<String>) -> Vec<(String,String)> {
-        let mut placeholders = String::new();
-        let mut i = 0;
-        let filtered = dirs.iter().filter(|p|{
-                let needed = self.done.insert(p.to_string());
-                match (needed, &self.sql) {
-                    (true, Some(_)) => {
-                        i += 1;
-                        placeholders = format!("{}{}?{}", placeholders, if i>1 {","} else {""}, i);
-                    },
-                    (_,_) => {},
-                }
-                needed
-            }).map(|s|s.as_str()).collect::<Vec::<&str>>();
-        if filtered.is_empty() { return Vec::new(); }
+    async fn update(&mut self, dirs: &[String]) -> Semantics {
+        let filtered = dirs.iter().filter(|p|self.done.insert(p.to_string()))
+            .map(|s|s.as_str()).collect::<Vec::<&str>>();
+        if filtered.is_empty() { return Default::default(); }
 
         if let Some(ref mut prox) = self.proxy {
             match prox.update(&filtered).await {
@@ -567,19 +989,35 @@ impl<'a> IndexProxy<'a> {
                 },
             }
         } 
-        if let Some(sql) = &self.sql {
-            let qtext = format!("select concat(dir, '/', fname), description from descriptions where dir in ({})", placeholders);
-            let mut query = match sql.prepare(qtext.as_str()) {
-                Ok(q) => q,
-                Err(_) => return Vec::new(),
-            };
-            query.query_map(rusqlite::params_from_iter(filtered.iter()), |row|{
-                Ok((row.get(0).unwrap(), row.get(1).unwrap()))
-            }).unwrap().map(|r|r.unwrap()).collect()
-        } else {
-            Vec::new()
+        match &self.sql {
+            Some(sql) => Self::get_semantics(sql, &filtered),
+            None => Default::default(),
         }
     }
+
+    /// Returns indexed (path, description, tags) and image palettes for files in dirs
+    fn get_semantics(sql: &rusqlite::Connection, dirs: &[&str]) -> Semantics {
+        let placeholders = (1..=dirs.len()).map(|i|format!("?{}", i)).join(",");
+        let qtext = format!("select concat(dir, '/', fname), coalesce(user_description, description, ''), coalesce(tags, '')
+                            from descriptions where dir in ({})", placeholders);
+        let mut query = match sql.prepare(qtext.as_str()) {
+            Ok(q) => q,
+            Err(_) => return Default::default(),
+        };
+        let semantics = query.query_map(rusqlite::params_from_iter(dirs.iter()), |row|{
+            Ok((row.get(0).unwrap(), row.get(1).unwrap(), row.get(2).unwrap()))
+        }).unwrap().map(|r|r.unwrap()).collect();
+        let qtext = format!("select concat(dir, '/', fname), palette from thumbinfo
+                            where dir in ({}) and palette is not null", placeholders);
+        let palettes = match sql.prepare(qtext.as_str()) {
+            Ok(mut query) => query.query_map(rusqlite::params_from_iter(dirs.iter()), |row|{
+                let palette: String = row.get(1)?;
+                Ok((row.get(0)?, palette.split(',').filter_map(parse_color).collect()))
+            }).map(|rows|rows.filter_map(|r|r.ok()).collect()).unwrap_or_default(),
+            Err(_) => vec![],
+        };
+        (semantics, palettes)
+    }
 }
 
 enum Preview {
@@ -652,6 +1090,7 @@ struct FilePicker {
     searchbar: String,
     search_running: bool,
     recurse_state: RecState,
+    dirs_scanned: usize,
     thumb_sender: Option<USender<FItem>>,
     nproc: usize,
     last_loaded: usize,
@@ -663,6 +1102,10 @@ struct FilePicker {
     nav_id: u8,
     view_id: u8,
     show_hidden: bool,
+    /// image from `pikeru view` to show once it's loaded
+    pending_view: Option<String>,
+    /// what's typed into the filter menu, which may not parse yet
+    filter_text: [String; 3],
     view_image: (usize, Preview),
     scroll_offset: scrollable::AbsoluteOffset,
     ino_updater: Option<USender<Inochan>>,
@@ -671,12 +1114,29 @@ struct FilePicker {
     save_filename: Option<String>,
     select_button: String,
     new_dir: String,
+    save_search_name: String,
+    edit_tags: String,
+    edit_desc: String,
+    db: Option<rusqlite::Connection>,
     modal: FModal,
     dir_history: Vec<Vec<String>>,
+    file_list: Option<Vec<String>>,
+    /// sort and filter for the next LoadDir, from a saved search
+    saved_view: Option<(i32, Filter)>,
     content_viewport: Rectangle,
     content_y: f32,
     content_height: f32,
     recursive_search: bool,
+    /// every match of the last name search, best first
+    search_results: Vec<(usize, i64)>,
+    /// how many of search_results are in the grid
+    search_shown: usize,
+    /// search greps inside text files instead of matching names
+    content_search: bool,
+    content_cancel: Option<Arc<AtomicBool>>,
+    content_sender: Option<USender<SearchEvent>>,
+    /// where each shown search result matched, by items index
+    hits: HashMap<usize, Hit>,
     show_goto: bool,
     enable_sel_button: bool,
     row_sizes: RefCell<RowSizes>,
@@ -691,15 +1151,15 @@ impl Application for FilePicker {
 
     fn new(conf: Self::Flags) -> (Self, iced::Command<Self::Message>) {
         let pathstr = conf.path.clone();
-        let path = Path::new(&pathstr);
+        let path = rawpath::to_path(&pathstr);
         let mut window_size = conf.window_size;
         window_size.width *= conf.dpi_scale as f32;
         window_size.height *= conf.dpi_scale as f32;
         let startdir = if path.is_dir() {
-            path.to_string_lossy().to_string()
+            pathstr.clone()
         } else {
             match path.parent() {
-                Some(pth) => pth.to_string_lossy().to_string(),
+                Some(pth) => rawpath::to_str(pth),
                 None => conf.home.as_str().to_string(),
             }
         };
@@ -708,7 +1168,7 @@ impl Application for FilePicker {
             if path.is_dir() {
                 None
             } else {
-                Some(path.file_name().unwrap().to_string_lossy().to_string())
+                Some(rawpath::to_str(path.file_name().unwrap()))
             }
         } else {
             None
@@ -716,9 +1176,16 @@ impl Application for FilePicker {
         let select_button = match conf.mode {
             Mode::Files|Mode::File => "Open",
             Mode::Save => "Save",
-            Mode::Dir => "Selecct",
+            Mode::Dir|Mode::Dirs|Mode::Any => "Select",
         }.to_string();
         let enable_sel_button = conf.saving();
+        let dirs = match &conf.stdin_list {
+            Some(list) if !list.is_empty() => list.iter().filter_map(|p|Path::new(p).parent())
+                .map(|p|p.to_string_lossy().to_string()).unique().collect(),
+            _ => vec![startdir],
+        };
+        let pending_view = conf.view.clone();
+        let filter_text = conf.filter().map_or(Default::default(), Filter::menu_text);
         (
             Self {
                 conf,
@@ -727,13 +1194,14 @@ impl Application for FilePicker {
                 displayed: vec![],
                 thumb_sender: None,
                 nproc: num_cpus::get() * 2,
-                dirs: vec![startdir],
+                dirs,
                 last_loaded: 0,
                 last_clicked: LastClicked::default(),
                 pathbar: String::new(),
                 searchbar: String::new(),
                 search_running: false,
-                recurse_state: RecState::Stop,
+                recurse_state: RecState::Idle,
+                dirs_scanned: 0,
                 icons: Arc::new(Icons::new(ts)),
                 clicktimer: ClickTimer{ idx:0, time: Instant::now() - Duration::from_secs(1), preclicked: None},
                 ctrl_pressed: false,
@@ -742,6 +1210,8 @@ impl Application for FilePicker {
                 nav_id: 0,
                 view_id: 0,
                 show_hidden: false,
+                pending_view,
+                filter_text,
                 view_image: (0, Preview::None),
                 scroll_offset: scrollable::AbsoluteOffset{x: 0.0, y: 0.0},
                 ino_updater: None,
@@ -751,11 +1221,23 @@ impl Application for FilePicker {
                 select_button,
                 modal: FModal::None,
                 new_dir: String::new(),
+                save_search_name: String::new(),
+                edit_tags: String::new(),
+                edit_desc: String::new(),
+                db: IndexProxy::open_db(),
                 dir_history: vec![],
+                file_list: None,
+                saved_view: None,
                 content_viewport: Rectangle::default(),
                 content_height: 0.0,
                 content_y: 0.0,
                 recursive_search: true,
+                search_results: vec![],
+                search_shown: SEARCH_PAGE,
+                content_search: false,
+                content_cancel: None,
+                content_sender: None,
+                hits: HashMap::new(),
                 show_goto: false,
                 enable_sel_button,
                 row_sizes: RefCell::new(RowSizes::new()),
@@ -774,16 +1256,26 @@ impl Application for FilePicker {
     }
 
     fn update(&mut self, message: Self::Message) -> iced::Command<Self::Message> {
+        // a list from stdin has no directories to navigate
+        if self.conf.listing() && matches!(message, Message::LoadBookmark(_) | Message::LoadRecent | Message::LoadSavedSearch(_) | Message::Goto |
+                                           Message::DownDir | Message::UpDir | Message::NewDir(_)) {
+            return Command::none();
+        }
         match message {
             Message::Thumbsize(size) => {
                 self.conf.thumb_size = (size / 5.0).round() * 5.0;
                 self.conf.need_update = true;
             },
             Message::InoCreate(file) => {
+                if !self.conf.can_browse(&file) {
+                    return Command::none();
+                }
                 let mut item = FItem::new(file.as_str().into(), self.nav_id);
                 let len = self.items.len();
                 item.display_idx = self.displayed.len();
-                self.displayed.push(len);
+                if self.visible(&item) {
+                    self.displayed.push(len);
+                }
                 self.items.push(FItem::default());
                 self.end_idx += 1;
                 item.items_idx = len;
@@ -801,39 +1293,74 @@ impl Application for FilePicker {
                     self.items.remove(i);
                     self.end_idx -= 1;
                     self.displayed.remove(dix);
-                    self.update_searcher_items(self.items.iter().map(|item|item.path.clone()).collect());
+                    self.search_results.retain(|m| m.0 != i);
+                    self.search_results.iter_mut().for_each(|m| if m.0 > i { m.0-=1 });
+                    self.hits = self.hits.drain().filter(|(ii, _)| *ii != i)
+                        .map(|(ii, hit)| (if ii > i { ii-1 } else { ii }, hit)).collect();
+                    self.update_searcher_items(self.items.iter().map(|item|item.searchable()).collect());
                 }
             },
             Message::RunCmd(i) => self.run_command(i),
             Message::Dummy => {},
             Message::SetRecursive(rec) => {
                 self.recursive_search = rec;
-                self.recurse_updater.as_ref().unwrap().send(RecMsg::SetRecursive(rec)).unwrap();
-                if !rec { // reset searchable items in case already recursed
-                    let items = self.items[..self.end_idx].iter().map(|item|item.path.clone()).collect::<Vec<_>>();
-                    let iidxs = self.items[..self.end_idx].iter().map(|item|item.items_idx).collect::<Vec<_>>();
-                    if let Some(ref mut sender) = self.search_commander {
-                        sender.send(SearchEvent::NewItems(items, self.nav_id)).unwrap();
-                        sender.send(SearchEvent::NewView(iidxs)).unwrap();
+                if !rec && self.recurse_state == RecState::Run {
+                    let _ = self.update(Message::StopRecurse);
+                }
+                if rec && self.recurse_state == RecState::Stopped {
+                    self.reset_walk();
+                }
+                // recursed items stay with the searcher so indexes line up, they're just not searched
+                let end = if rec { self.items.len() } else { self.end_idx };
+                let view = (0..end).filter(|i|self.visible(&self.items[*i])).collect();
+                if let Some(ref mut sender) = self.search_commander {
+                    sender.send(SearchEvent::NewView(view)).unwrap();
+                }
+                return self.update(Message::SearchTxtInput(self.searchbar.clone()));
+            },
+            Message::RecurseProgress(scanned, done, nav_id) => {
+                if nav_id == self.nav_id {
+                    self.dirs_scanned = scanned;
+                    if done && self.recurse_state == RecState::Run {
+                        self.recurse_state = RecState::Done;
                     }
                 }
             },
+            Message::StopRecurse => {
+                self.recurse_updater.as_ref().unwrap().send(RecMsg::Stop(self.nav_id)).unwrap();
+                self.recurse_state = RecState::Stopped;
+            },
+            Message::SetContentSearch(on) => {
+                self.content_search = on;
+                return self.update(Message::SearchTxtInput(self.searchbar.clone()));
+            },
             Message::ShowHidden(show) => {
                 self.show_hidden = show;
-                if !show {
-                    self.enable_sel_button = self.conf.saving() || self.items.iter().any(|item|item.sel);
-                }
-                let end = if self.searchbar.is_empty() { self.end_idx } else { self.displayed.len() };
-                let displayed = self.items[..end].iter().enumerate().filter_map(|(i,item)| {
-                    if show || !item.hidden { Some(i)
-                    } else { None }
-                }).collect();
-                if self.searchbar.is_empty() {
-                    self.displayed = displayed;
-                    return self.update(Message::Sort(self.conf.sort_by));
-                } else {
-                    self.update_searcher_visible(displayed);
+                return self.refresh_visible();
+            },
+            Message::FilterKind(kind, on) => {
+                self.conf.filter_mut().toggle(kind, on);
+                return self.refresh_visible();
+            },
+            Message::FilterInput(i, txt) => {
+                let val = txt.trim();
+                let filter = self.conf.filter_mut();
+                // keep the last working value while the text doesn't parse
+                match i {
+                    0 if val.is_empty() => filter.min_size = None,
+                    0 => if let Some(size) = policy::parse_size(val) { filter.min_size = Some(size) },
+                    1 if val.is_empty() => filter.max_size = None,
+                    1 => if let Some(size) = policy::parse_size(val) { filter.max_size = Some(size) },
+                    _ if val.is_empty() => filter.max_age = None,
+                    _ => if let Ok(days) = val.parse() { filter.max_age = Some(days) },
                 }
+                self.filter_text[i] = txt;
+                return self.refresh_visible();
+            },
+            Message::ClearFilter => {
+                *self.conf.filter_mut() = Filter::default();
+                self.filter_text = Default::default();
+                return self.refresh_visible();
             },
             Message::Sort(i) => {
                 match i {
@@ -860,8 +1387,7 @@ impl Application for FilePicker {
                     _ => unreachable!(),
                 };
                 self.displayed.iter().enumerate().for_each(|(i,j)|unsafe{self.items.get_unchecked_mut(*j)}.display_idx = i);
-                self.conf.need_update |= i != self.conf.sort_by;
-                self.conf.sort_by = i;
+                self.conf.set_sort_by(i);
                 self.row_sizes.borrow_mut().reset(true);
                 return self.update(Message::LoadThumbs);
             },
@@ -911,6 +1437,17 @@ impl Application for FilePicker {
             },
             Message::Scrolled(viewport) => {
                 self.update_scroll(viewport.absolute_offset().y);
+                let below = viewport.content_bounds().height - viewport.bounds().height - viewport.absolute_offset().y;
+                if below < viewport.bounds().height && self.displayed.len() < self.search_results.len() && !self.content_search {
+                    self.search_shown = self.displayed.len() + SEARCH_PAGE;
+                    self.show_search_results();
+                    return self.update(Message::LoadThumbs);
+                }
+            },
+            Message::ShowAllResults => {
+                self.search_shown = self.search_results.len();
+                self.show_search_results();
+                return self.update(Message::LoadThumbs);
             },
             Message::PageUp => {
                 let current = self.scroll_offset.y;
@@ -956,10 +1493,10 @@ impl Application for FilePicker {
                 let (txrec, recurse_cmds) = unbounded_channel::<RecMsg>();
                 tokio::spawn(watch_inotify(watch_cmds, inochan));
                 self.search_commander = Some(txsrch.clone());
+                self.content_sender = Some(search_res.clone());
                 self.ino_updater = Some(txino);
                 self.thumb_sender = Some(fichan);
-                tokio::spawn(recursive_add(recurse_cmds, more_files, txrec.clone(), txsrch,
-                                           self.conf.gitignore.clone(), self.conf.respect_gitignore));
+                tokio::spawn(recursive_add(recurse_cmds, more_files, txrec.clone(), txsrch, self.conf.walk_opts()));
                 self.recurse_updater = Some(txrec);
                 tokio::spawn(search_loop(search_cmds, search_res));
                 return self.update(Message::LoadDir);
@@ -967,42 +1504,70 @@ impl Application for FilePicker {
             Message::PathTxtInput(txt) => self.pathbar = txt,
             Message::SearchTxtInput(txt) => {
                 self.searchbar = txt;
+                self.stop_content_search();
+                self.search_results.clear();
+                self.search_shown = SEARCH_PAGE;
                 if self.searchbar.is_empty() {
-                    self.recurse_state = RecState::Stop;
+                    self.reset_walk();
                     let mut have_sel = false;
                     self.displayed = self.items[..self.end_idx].iter().enumerate().filter_map(|(i,item)| {
-                        if self.show_hidden || !item.hidden {
+                        if self.visible(item) {
                             have_sel |= item.sel;
                             Some(i)
                         } else {None}
                     }).collect();
                     self.show_goto = have_sel && self.dirs.len() > 1;
                     self.enable_sel_button = self.conf.saving() || have_sel;
-                    return self.update(Message::Sort(self.conf.sort_by));
-                } else if !self.search_running{
-                    self.search_running = true;
-                    self.search_commander.as_ref().unwrap().send(SearchEvent::Search(self.searchbar.clone())).unwrap();
-                    if self.recurse_state != RecState::Run {
+                    return self.update(Message::Sort(self.conf.sort_by()));
+                } else if self.content_search {
+                    self.start_content_search();
+                } else {
+                    if !self.search_running {
+                        self.search_running = true;
+                        self.search_commander.as_ref().unwrap().send(SearchEvent::Search(self.searchbar.clone())).unwrap();
+                    }
+                    // the walk keeps going once started, until it's stopped or the dirs change
+                    if self.recursive_search && self.recurse_state == RecState::Idle {
                         self.recurse_state = RecState::Run;
-                        self.recurse_updater.as_ref().unwrap().send(RecMsg::FetchMore(self.nav_id, true)).unwrap();
+                        self.recurse_updater.as_ref().unwrap().send(RecMsg::Start(self.nav_id)).unwrap();
                     }
                 }
             },
             Message::SearchResult(res) => {
                 let mut still_running = false;
-                if let SearchEvent::Results(res, nav_id, num_items, term) = *res {
-                    if nav_id == self.nav_id && !self.searchbar.is_empty() {
-                        self.displayed = res[..1000.min(res.len())].into_iter().enumerate().map(|(di,ii)|{
-                            self.items[ii.0].display_idx = di;
-                            ii.0
-                        }).collect();
-                        let _ = self.update(Message::LoadThumbs);
-                        if term != self.searchbar || num_items != self.items.len() {
-                            self.search_commander.as_ref().unwrap().send(SearchEvent::Search(self.searchbar.clone())).unwrap();
-                            still_running = true;
+                let (res, more, nav_id, num_items, term) = match *res {
+                    SearchEvent::ContentMatches(matches, nav_id, term) => {
+                        if nav_id == self.nav_id && term == self.searchbar && self.content_search {
+                            return self.add_content_matches(matches);
+                        }
+                        return Command::none();
+                    },
+                    SearchEvent::Hits(hits, nav_id, term) => {
+                        if nav_id == self.nav_id && term == self.searchbar && !self.content_search {
+                            self.hits.extend(hits);
                         }
-                        self.row_sizes.borrow_mut().reset(true);
+                        return Command::none();
+                    },
+                    SearchEvent::Results(res, nav_id, num_items, term) => (res, false, nav_id, num_items, term),
+                    SearchEvent::MoreResults(res, nav_id, num_items, term) => (res, true, nav_id, num_items, term),
+                    _ => return Command::none(),
+                };
+                if nav_id == self.nav_id && !self.searchbar.is_empty() && !self.content_search {
+                    self.search_results = if more {
+                        mem::take(&mut self.search_results).into_iter().merge_by(res, |a, b| a.1 >= b.1).collect()
+                    } else { res };
+                    self.show_search_results();
+                    let _ = self.update(Message::LoadThumbs);
+                    if term != self.searchbar || num_items != self.items.len() {
+                        let searchbar = self.searchbar.clone();
+                        self.search_commander.as_ref().unwrap().send(if term == searchbar {
+                            SearchEvent::SearchMore(searchbar)
+                        } else {
+                            SearchEvent::Search(searchbar)
+                        }).unwrap();
+                        still_running = true;
                     }
+                    self.row_sizes.borrow_mut().reset(true);
                 }
                 self.search_running = still_running;
             },
@@ -1011,20 +1576,17 @@ impl Application for FilePicker {
                     let mut new_displayed = vec![];
                     let paths = next_items.iter_mut().enumerate().map(|(i,fitem)| {
                         fitem.items_idx = self.items.len() + i;
-                        if self.show_hidden || !fitem.hidden {
+                        if self.recursive_search && self.visible(fitem) {
                             new_displayed.push(fitem.items_idx);
                         }
-                        fitem.path.clone()
+                        fitem.searchable()
                     }).collect();
                     let sender = self.search_commander.as_ref().unwrap();
                     self.items.append(&mut next_items);
                     sender.send(SearchEvent::AddItems(paths)).unwrap();
                     sender.send(SearchEvent::AddView(new_displayed)).unwrap();
-                    if !self.search_running {
-                        sender.send(SearchEvent::Search(self.searchbar.clone())).unwrap();
-                    }
-                    if self.recurse_state != RecState::Stop {
-                        self.recurse_updater.as_ref().unwrap().send(RecMsg::FetchMore(nav_id, true)).unwrap();
+                    if !self.search_running && !self.searchbar.is_empty() && !self.content_search {
+                        sender.send(SearchEvent::SearchMore(self.searchbar.clone())).unwrap();
                     }
                 }
             },
@@ -1039,18 +1601,24 @@ impl Application for FilePicker {
                         ArrowDown => i + maxcols,
                         ArrowLeft => i - 1,
                         ArrowRight => i + 1,
+                        Home => 0,
+                        End => self.displayed.len() as i64 - 1,
                         _ => -1,
                     }
                 } else { 0 };
                 match self.view_image.1 {
                     Preview::None => {},
                     _ => {
-                        let step = didx - (self.last_clicked.didx as i64);
+                        let step = match key {
+                            Home => i64::MIN,
+                            End => i64::MAX,
+                            _ => didx - (self.last_clicked.didx as i64),
+                        };
                         return self.update(Message::NextImage(step));
                     },
                 }
                 if didx >= 0 && didx < self.displayed.len() as i64 {
-                    self.click_item(self.dtoi(didx as usize), self.shift_pressed, self.ctrl_pressed, false);
+                    return self.click_item(self.dtoi(didx as usize), self.shift_pressed, self.ctrl_pressed, false);
                 }
             },
             Message::LoadThumbs => {
@@ -1087,9 +1655,19 @@ impl Application for FilePicker {
                         }
                         self.last_loaded = prev_di + 1;
                     }
+                    if let (Some(phash), Some(sql)) = (doneitem.phash, &self.db) {
+                        let info = ThumbInfo { phash: Some(phash), palette: doneitem.palette.clone() };
+                        IndexProxy::save_thumbinfo(sql, &doneitem.path, doneitem.mtime, &info);
+                        if let Some(ref sender) = self.search_commander {
+                            sender.send(SearchEvent::AddPalettes(vec![(doneitem.path.clone(), info.palette)])).unwrap();
+                        }
+                    }
                     let j = doneitem.items_idx;
                     doneitem.display_idx = self.items[j].display_idx;
                     self.items[j] = doneitem;
+                    if self.pending_view.is_some() {
+                        return self.open_pending_view();
+                    }
                 }
             },
             Message::LoadBookmark(idx) => {
@@ -1098,6 +1676,18 @@ impl Application for FilePicker {
                 self.update_scroll(0.0);
                 return self.update(Message::LoadDir);
             },
+            Message::LoadRecent => {
+                let paths = self.db.as_ref().map(IndexProxy::get_uploads).unwrap_or_default();
+                self.dir_history.push(mem::take(&mut self.dirs));
+                self.dirs = paths.iter().filter_map(|p|Path::new(p).parent())
+                    .map(|p|p.to_string_lossy().to_string()).unique().collect();
+                if self.dirs.is_empty() {
+                    self.dirs.push(self.conf.home.clone());
+                }
+                self.file_list = Some(paths);
+                self.update_scroll(0.0);
+                return self.update(Message::LoadDir);
+            },
             Message::Goto => {
                 self.dir_history.push(mem::take(&mut self.dirs));
                 self.dirs = self.items.iter().filter(|item|item.sel).map(|item|
@@ -1106,17 +1696,39 @@ impl Application for FilePicker {
                 return self.update(Message::LoadDir);
             }
             Message::LoadDir => {
+                // a saved search's sort and filter only last until the next dir
+                let saved_view = self.saved_view.take();
+                if saved_view.is_some() || self.conf.saved_view.is_some() {
+                    self.conf.saved_view = saved_view;
+                    self.filter_text = self.conf.filter().map_or(Default::default(), Filter::menu_text);
+                }
+                self.dirs.retain(|dir| self.conf.can_browse(dir));
+                if self.dirs.is_empty() {
+                    let start = self.conf.policy.as_ref().and_then(|p|p.start_dir()).unwrap_or(Path::new("/"));
+                    self.dirs.push(rawpath::to_str(start));
+                }
                 self.view_image = (0, Preview::None);
                 self.update_scroll(0.0);
                 self.pathbar = match &self.save_filename {
                     Some(fname) => Path::new(&self.dirs[0]).join(fname).to_string_lossy().to_string(),
                     None => self.dirs[0].clone(),
                 };
-                self.load_dir();
+                // listed files have no directory to recurse into
+                let navdirs = match self.file_list.take().or_else(|| self.conf.stdin_list.clone()) {
+                    Some(paths) => { self.load_list(paths); vec![] },
+                    None => { self.load_dir(); self.dirs.clone() },
+                };
                 self.show_goto = false;
-                self.recurse_state = RecState::Stop;
-                self.recurse_updater.as_ref().unwrap().send(RecMsg::NewNav(self.dirs.clone(), self.nav_id)).unwrap();
-                let _ = self.update(Message::Sort(self.conf.sort_by));
+                self.recurse_state = RecState::Idle;
+                self.dirs_scanned = 0;
+                self.recurse_updater.as_ref().unwrap().send(RecMsg::NewNav(navdirs, self.nav_id)).unwrap();
+                let _ = self.update(Message::Sort(self.conf.sort_by()));
+                // load the image to view first instead of waiting for everything before it in the grid
+                if let Some(i) = self.pending_view.as_ref().and_then(|view|self.items.iter().position(|item|item.path == *view)) {
+                    let di = self.itod(i);
+                    let item = mem::replace(&mut self.items[i], FItem::placeholder(i, di));
+                    tokio::spawn(item.load(self.thumb_sender.as_ref().unwrap().clone(), self.icons.clone(), self.conf.thumb_size as u32));
+                }
                 return scrollable::snap_to(self.scroll_id.clone(), scrollable::RelativeOffset::START);
             },
             Message::DownDir => {
@@ -1139,7 +1751,7 @@ impl Application for FilePicker {
                 return self.update(Message::LoadDir);
             },
             Message::NewDir(confirmed) => if confirmed {
-                    let path = Path::new(&self.dirs[0]).join(&self.new_dir);
+                    let path = rawpath::to_path(&self.dirs[0]).join(&self.new_dir);
                     if let Err(e) = std::fs::create_dir_all(&path) {
                         let msg = format!("Error creating directory: {:?}", e);
                         self.modal = FModal::Error(msg);
@@ -1150,12 +1762,179 @@ impl Application for FilePicker {
                     self.modal = FModal::NewDir;
                 },
             Message::NewDirInput(dir) => self.new_dir = dir,
+            Message::LoadSavedSearch(idx) => {
+                let saved = self.conf.saved_searches[idx].clone();
+                self.dir_history.push(mem::take(&mut self.dirs));
+                self.dirs = saved.dirs;
+                self.recursive_search = saved.recursive;
+                self.content_search = saved.content;
+                self.saved_view = Some((saved.sort_by, saved.filter));
+                self.update_scroll(0.0);
+                let _ = self.update(Message::LoadDir);
+                return self.update(Message::SearchTxtInput(saved.query));
+            },
+            Message::SaveSearch(confirmed) => if confirmed {
+                let name = self.save_search_name.trim().replace('=', "-");
+                let label = if name.is_empty() { self.searchbar.clone() } else { name };
+                let saved = SavedSearch {
+                    label: label.clone(),
+                    dirs: self.dirs.clone(),
+                    query: self.searchbar.clone(),
+                    recursive: self.recursive_search,
+                    content: self.content_search,
+                    sort_by: self.conf.sort_by(),
+                    filter: self.conf.filter().cloned().unwrap_or_default(),
+                };
+                // saving under an existing name replaces it
+                match self.conf.saved_searches.iter().position(|s|s.label == label) {
+                    Some(i) => self.conf.saved_searches[i] = saved,
+                    None => self.conf.saved_searches.push(saved),
+                }
+                self.conf.update(true);
+                self.modal = FModal::None;
+            } else {
+                self.save_search_name.clear();
+                self.modal = FModal::SaveSearch;
+            },
+            Message::SaveSearchInput(name) => self.save_search_name = name,
+            Message::EditTags => {
+                let sels = self.items.iter().filter(|item|item.sel).collect::<Vec<_>>();
+                match (&self.db, sels.len()) {
+                    (None, _) if !self.conf.write_xattrs => self.modal = FModal::Error("Index database is unavailable".to_string()),
+                    (_, 0) => {},
+                    (sql, n) => {
+                        (self.edit_tags, self.edit_desc) = Default::default();
+                        if n == 1 {
+                            if let Some(sql) = sql {
+                                (self.edit_tags, self.edit_desc) = IndexProxy::get_user_text(sql, &sels[0].path);
+                            }
+                            if self.edit_tags.is_empty() {
+                                self.edit_tags = sels[0].xdg_tags.clone().unwrap_or_default();
+                            }
+                            if self.edit_desc.is_empty() {
+                                self.edit_desc = sels[0].xdg_comment.clone().unwrap_or_default();
+                            }
+                        }
+                        self.modal = FModal::Tags(n);
+                    },
+                }
+            },
+            Message::FindSimilar => {
+                let target = self.last_clicked.iidx;
+                if target >= self.items.len() || !self.items[target].sel {
+                    return Command::none();
+                }
+                let hashes = self.lookup_hashes(&self.displayed);
+                let target_hash = self.lookup_hashes(&[target]).remove(&target);
+                let path = self.items[target].path.clone();
+                let thumbsize = self.conf.thumb_size as u32;
+                let nav_id = self.nav_id;
+                // the picked image is decoded off the ui thread when it has no hash yet
+                let hashing = tokio::task::spawn_blocking(move || {
+                    let target_hash = target_hash.or_else(||file_thumbinfo(&path, thumbsize)?.phash)?;
+                    Some(hashes.into_iter().map(|(i, h)|(i, (h ^ target_hash).count_ones())).collect::<HashMap<_, _>>())
+                });
+                let path = self.items[target].path.clone();
+                return Command::perform(async move {
+                    hashing.await.ok().flatten()
+                }, move |distances| Message::Similar(distances, path, nav_id));
+            },
+            Message::Similar(distances, path, nav_id) => {
+                if nav_id != self.nav_id {
+                    return Command::none();
+                }
+                let Some(distances) = distances else {
+                    self.modal = FModal::Error(format!("Can't compute image hash of {}", path));
+                    return Command::none();
+                };
+                self.displayed.sort_by_cached_key(|i| distances.get(i).copied().unwrap_or(u32::MAX));
+                self.displayed.iter().enumerate().for_each(|(i,j)|self.items[*j].display_idx = i);
+                self.row_sizes.borrow_mut().reset(true);
+                return self.update(Message::LoadThumbs);
+            },
+            Message::FindDuplicates => {
+                let candidates = self.items.iter().filter(|item| is_image_ext(&item.path)
+                    && (self.show_hidden || !item.hidden)).map(|item|item.items_idx).collect::<Vec<_>>();
+                let mut known = self.lookup_hashes(&candidates);
+                let images = candidates.into_iter().map(|i|DupImage {
+                    idx: i,
+                    path: self.items[i].path.clone(),
+                    mtime: self.items[i].mtime,
+                    phash: known.remove(&i),
+                }).collect::<Vec<_>>();
+                let (thumbsize, save) = (self.conf.thumb_size as u32, self.db.is_some());
+                let nav_id = self.nav_id;
+                let grouping = tokio::task::spawn_blocking(move || find_duplicates(images, thumbsize, save));
+                return Command::perform(async move {
+                    grouping.await.unwrap_or_default()
+                }, move |displayed| Message::Duplicates(displayed, nav_id));
+            },
+            Message::Location(found, nav_id) => {
+                if nav_id == self.nav_id {
+                    for (i, gps) in found {
+                        self.items[i].gps = Some(gps);
+                    }
+                    self.last_clicked.location = self.items.iter().any(|item| item.sel && item.gps == Some(true));
+                }
+            },
+            Message::Duplicates(displayed, nav_id) => {
+                if nav_id != self.nav_id {
+                    return Command::none();
+                }
+                if displayed.is_empty() {
+                    self.modal = FModal::Error("No duplicate images found".to_string());
+                    return Command::none();
+                }
+                displayed.iter().enumerate().for_each(|(i,j)|self.items[*j].display_idx = i);
+                self.displayed = displayed;
+                self.row_sizes.borrow_mut().reset(true);
+                self.update_scroll(0.0);
+                let _ = self.update(Message::LoadThumbs);
+                return scrollable::snap_to(self.scroll_id.clone(), scrollable::RelativeOffset::START);
+            },
+            Message::TagsInput(tags) => self.edit_tags = tags,
+            Message::UserDescInput(desc) => self.edit_desc = desc,
+            Message::SaveTags => {
+                let FModal::Tags(n) = self.modal else { return Command::none() };
+                self.modal = FModal::None;
+                let edit = TagEdit {
+                    tags: mem::take(&mut self.edit_tags),
+                    desc: mem::take(&mut self.edit_desc),
+                    multi: n > 1,
+                    xattrs: self.conf.write_xattrs,
+                    db: self.db.is_some(),
+                };
+                let files = self.items.iter().filter(|item|item.sel).map(|item|(item.items_idx, item.path.clone())).collect::<Vec<_>>();
+                let nav_id = self.nav_id;
+                let saving = tokio::task::spawn_blocking(move || edit.save(files));
+                return Command::perform(async move {
+                    saving.await.unwrap_or_else(|e| SavedTags { error: Some(e.to_string()), ..Default::default() })
+                }, move |saved| Message::TagsSaved(saved, nav_id));
+            },
+            Message::TagsSaved(saved, nav_id) => {
+                if nav_id == self.nav_id {
+                    let attrs = saved.xattrs.into_iter().map(|(i, tags, comment)| {
+                        self.items[i].xdg_tags = tags;
+                        self.items[i].xdg_comment = comment;
+                        (i, self.items[i].searchable().xattrs)
+                    }).collect::<Vec<_>>();
+                    if let Some(ref sender) = self.search_commander {
+                        sender.send(SearchEvent::SetAttrs(attrs)).unwrap();
+                    }
+                }
+                if let Some(ref sender) = self.search_commander {
+                    sender.send(SearchEvent::AddSemantics(saved.semantics)).unwrap();
+                }
+                if let Some(e) = saved.error {
+                    self.modal = FModal::Error(e);
+                }
+            },
             Message::CloseModal => self.modal = FModal::None,
-            Message::MiddleClick(iidx) => self.click_item(iidx, false, true, false),
+            Message::MiddleClick(iidx) => return self.click_item(iidx, false, true, false),
             Message::LeftPreClick(iidx) => self.clicktimer.preclick(iidx),
             Message::LeftClick(iidx, always_valid) => {
                 match self.clicktimer.click(iidx, always_valid) {
-                    ClickType::Single => self.click_item(iidx, self.shift_pressed, self.ctrl_pressed, iidx == self.view_image.0),
+                    ClickType::Single => return self.click_item(iidx, self.shift_pressed, self.ctrl_pressed, iidx == self.view_image.0),
                     ClickType::Double => {
                         self.items[iidx].sel = true;
                         return self.update(Message::Select(SelType::Click));
@@ -1169,9 +1948,9 @@ impl Application for FilePicker {
                     let item = &self.items[iidx];
                     if item.ftype == FType::Image {
                         self.view_image = (item.items_idx, item.preview());
-                        self.click_item(iidx, false, false, true);
+                        return self.click_item(iidx, false, false, true);
                     } else {
-                        self.click_item(iidx, true, false, false);
+                        return self.click_item(iidx, true, false, false);
                     }
                 } else {
                     self.view_image = (0, Preview::None);
@@ -1182,7 +1961,12 @@ impl Application for FilePicker {
                 match self.view_image.1 {
                     Preview::None => {},
                     _ => {
-                        let mut didx = self.itod(self.view_image.0) as i64;
+                        // i64::MIN and i64::MAX look for the first and last image
+                        let (mut didx, step) = match step {
+                            i64::MIN => (-1, 1),
+                            i64::MAX => (self.displayed.len() as i64, -1),
+                            _ => (self.itod(self.view_image.0) as i64, step),
+                        };
                         while (step<0 && didx>0) || (step>0 && didx<((self.displayed.len()-1) as i64)) {
                             didx = (didx as i64) + step;
                             if didx<0 || didx as usize>=self.displayed.len() {
@@ -1212,14 +1996,15 @@ impl Application for FilePicker {
                 }
             },
             Message::OverWriteOK => {
-                println!("{}", self.pathbar);
-                self.exit();
+                self.finish(vec![self.pathbar.clone()]);
             },
             Message::Select(seltype) => {
                 if self.conf.saving() {
                     if !self.pathbar.is_empty() {
-                        let result = Path::new(&self.pathbar);
-                        if result.is_file() {
+                        let result = rawpath::to_path(&self.pathbar);
+                        if !result.is_dir() && !self.conf.can_select(&self.pathbar) {
+                            self.modal = FModal::Error(format!("Saving to {} is not allowed for this application", self.pathbar));
+                        } else if result.is_file() {
                             self.modal = FModal::OverWrite;
                         } else if result.is_dir() {
                             self.dir_history.push(mem::take(&mut self.dirs));
@@ -1227,22 +2012,29 @@ impl Application for FilePicker {
                             self.update_scroll(0.0);
                             return self.update(Message::LoadDir);
                         } else {
-                            println!("{}", self.pathbar);
-                            self.exit();
+                            self.finish(vec![self.pathbar.clone()]);
                         }
                     }
                 } else {
-                    let pb =  FItem::new(PathBuf::from(&self.pathbar), self.nav_id);
+                    let pb =  FItem::new(rawpath::to_path(&self.pathbar).into_owned(), self.nav_id);
                     let sels: Vec<&FItem> = match seltype {
                         SelType::TxtEntr => vec![&pb],
                         _ => self.items.iter().filter(|item| item.sel ).collect(),
                     };
-                    if sels.len() != 0 {
+                    let blocked = sels.iter().find(|item| match item.ftype {
+                        FType::Dir => self.conf.dir() && seltype == SelType::Button && !self.conf.can_select(&item.path),
+                        FType::NotExist => false,
+                        _ => !self.conf.can_select(&item.path),
+                    });
+                    if let Some(item) = blocked {
+                        self.modal = FModal::Error(format!("{} is not allowed for this application", item.path));
+                    } else if !sels.is_empty() {
+                        let returns_dirs = seltype == SelType::Button && (self.conf.multi() || sels.len() == 1);
                         match sels[0].ftype {
                             FType::Dir => {
-                                if self.conf.dir() && sels.len() == 1 && seltype == SelType::Button {
-                                    println!("{}", sels[0].path);
-                                    self.exit();
+                                if (self.conf.dir() && returns_dirs) || self.conf.listing() {
+                                    let paths = sels.iter().map(|item|item.path.clone()).collect();
+                                    self.finish(paths);
                                 } else {
                                     self.dirs = sels.iter().filter_map(|item| match item.ftype {
                                         FType::Dir => Some(item.path.clone()), _ => None}).collect();
@@ -1251,13 +2043,16 @@ impl Application for FilePicker {
                             },
                             FType::NotExist => {},
                             _ => {
-                                println!("{}", sels.iter().map(|item|item.path.as_str()).join("\n"));
-                                self.exit();
+                                let paths = sels.iter().map(|item|item.path.clone()).collect();
+                                self.finish(paths);
                             }
                         }
                     }
                 }
             },
+            Message::CloseView => if !matches!(self.view_image.1, Preview::None) {
+                return self.update(Message::RightClick(-1));
+            },
             Message::Cancel => self.exit(),
         }
         Command::none()
@@ -1283,8 +2078,11 @@ impl Application for FilePicker {
                     SubState::Ready((thumb_recv, ino_recv, search_recv, rec_recv)) => {
                         tokio::select! {
                             more = rec_recv.recv() => {
-                                if let Some(RecMsg::NextItems(items, nav_id)) = more {
-                                    messager.send(Message::NextRecurse(items, nav_id)).await.unwrap();
+                                match more {
+                                    Some(RecMsg::NextItems(items, nav_id)) => messager.send(Message::NextRecurse(items, nav_id)).await.unwrap(),
+                                    Some(RecMsg::Progress(scanned, done, nav_id)) =>
+                                        messager.send(Message::RecurseProgress(scanned, done, nav_id)).await.unwrap(),
+                                    _ => {},
                                 }
                             },
                             res = search_recv.recv() => {
@@ -1323,6 +2121,9 @@ impl Application for FilePicker {
                     Keyboard(KeyPressed{ key: Key::Named(Backspace), .. }) => Some(Message::UpDir),
                     Keyboard(KeyPressed{ key: Key::Named(PageUp), .. }) => Some(Message::PageUp),
                     Keyboard(KeyPressed{ key: Key::Named(PageDown), .. }) => Some(Message::PageDown),
+                    Keyboard(KeyPressed{ key: Key::Named(Home), .. }) => Some(Message::ArrowKey(Home)),
+                    Keyboard(KeyPressed{ key: Key::Named(End), .. }) => Some(Message::ArrowKey(End)),
+                    Keyboard(KeyPressed{ key: Key::Named(Escape), .. }) => Some(Message::CloseView),
                     _ => None,
                 }
             } else { None }
@@ -1333,9 +2134,21 @@ impl Application for FilePicker {
     fn view(&self) -> iced::Element<'_, Self::Message> {
         responsive(|size| {
             let view_menu = |items| Menu::new(items).max_width(180.0).offset(15.0).spacing(3.0);
-            let cmd_list = self.conf.cmds.iter().enumerate().map(
-                |(i,cmd)|Item::new(menu_button(cmd.label.as_str(), Message::RunCmd(i)))).collect();
-            let bookmarks = self.conf.bookmarks.iter().enumerate().fold(column![], |col,(i,bm)| {
+            let cmd_list = [("Edit tags", Message::EditTags), ("Find similar", Message::FindSimilar),
+                            ("Find duplicates", Message::FindDuplicates)].into_iter()
+                .map(|(label, msg)|Item::new(menu_button(label, msg)))
+                .chain(self.conf.cmds.iter().enumerate().map(
+                    |(i,cmd)|Item::new(menu_button(cmd.label.as_str(), Message::RunCmd(i))))).collect();
+            let filter = self.conf.filter().cloned().unwrap_or_default();
+            let filter_label = if filter.is_empty() { "Filter" } else { "Filtered" };
+            let filter_list = Kind::ALL.into_iter()
+                .map(|kind|Item::new(checkbox(kind.label(), filter.kinds.contains(&kind))
+                                     .on_toggle(move |on|Message::FilterKind(kind, on))))
+                .chain(["Min size, like 100K", "Max size, like 20M", "Max age in days"].into_iter().enumerate().map(
+                    |(i, hint)|Item::new(TextInput::new(hint, &self.filter_text[i])
+                                         .on_input(move |txt|Message::FilterInput(i, txt)))))
+                .chain([Item::new(menu_button("Clear filters", Message::ClearFilter))]).collect();
+            let bookmarks = self.conf.bookmarks.iter().enumerate().filter(|_| !self.conf.listing()).fold(column![], |col,(i,bm)| {
                         col.push(Button::new(
                                     container(
                                         text(bm.label.as_str())
@@ -1346,7 +2159,33 @@ impl Application for FilePicker {
                                     .id(bm.id.clone()))
                                  .style(style::side_but_theme())
                                  .on_press(Message::LoadBookmark(i)))
-                    }).push(container(vertical_space()).height(Length::Fill).width(Length::Fill)
+                    });
+            let bookmarks = if self.conf.recent_uploads && !self.conf.listing() {
+                bookmarks.push(Button::new(
+                        container(
+                            text("Recent uploads")
+                                .size(15.0)
+                                .horizontal_alignment(alignment::Horizontal::Center)
+                                .width(Length::Fill))
+                        .padding(-3.0))
+                    .style(style::side_but_theme())
+                    .on_press(Message::LoadRecent))
+            } else { bookmarks };
+            let bookmarks = self.conf.saved_searches.iter().enumerate().filter(|_| !self.conf.listing()).fold(bookmarks, |col,(i,saved)| {
+                        col.push(tooltip(Button::new(
+                                    container(
+                                        text(saved.label.as_str())
+                                           .size(15.0)
+                                           .horizontal_alignment(alignment::Horizontal::Center)
+                                           .width(Length::Fill))
+                                    .padding(-3.0))
+                                 .style(style::side_but_theme())
+                                 .on_press(Message::LoadSavedSearch(i)),
+                                 container(text(format!("{} in {}", saved.query, saved.dirs.join(", "))).size(13))
+                                    .padding(8).style(Container::Box),
+                                 tooltip::Position::Right))
+                    });
+            let bookmarks = bookmarks.push(container(vertical_space()).height(Length::Fill).width(Length::Fill)
                             .id(CId::new("bookmarks"))).width(Length::Fixed(120.0));
 
             let mut clicked_offscreen = false;
@@ -1430,7 +2269,8 @@ impl Application for FilePicker {
                                     let item = &self.items[self.dtoi(idx)];
                                     row_all_ready &= item.thumb_handle != None;
                                     row_none_ready &= item.thumb_handle == None;
-                                    let (clicked, display) = item.display(&self.last_clicked, thumb_width);
+                                    let hit = self.hits.get(&item.items_idx);
+                                    let (clicked, display) = item.display(&self.last_clicked, thumb_width, hit);
                                     clicked_onscreen |= clicked;
                                     row = row.push(display);
                                 }
@@ -1459,18 +2299,55 @@ impl Application for FilePicker {
                         .id(self.scroll_id.clone()).into()
                 },
             };
-            let count = Text::new(format!("  {} items", self.displayed.len()));
+            let content = match self.view_image.1 {
+                Preview::None => content,
+                _ => {
+                    // the viewer runs [Commands] on the shown image without going through the menu
+                    let cmds = self.conf.cmds.iter().enumerate().filter(|_|self.conf.view.is_some())
+                        .fold(row![horizontal_space()], |row, (i, cmd)|row.push(top_button(&cmd.label, 100.0, Message::RunCmd(i))));
+                    column![
+                        content,
+                        cmds.push(top_button("Edit tags", 100.0, Message::EditTags)).spacing(1),
+                    ].into()
+                },
+            };
+            let paged = !self.searchbar.is_empty() && !self.content_search;
+            let progress = match self.recurse_state {
+                _ if !paged || !self.recursive_search => String::new(),
+                RecState::Idle => String::new(),
+                RecState::Run => format!(", scanned {} dirs", self.dirs_scanned),
+                RecState::Done => format!(" in {} dirs", self.dirs_scanned),
+                RecState::Stopped => format!(", stopped after {} dirs", self.dirs_scanned),
+            };
+            let mut count = if paged {
+                row![Text::new(format!("  {} of {} results{}", self.displayed.len(), self.search_results.len(), progress))]
+            } else {
+                row![Text::new(format!("  {} items", self.displayed.len()))]
+            }.spacing(5);
+            if paged && self.recursive_search && self.recurse_state == RecState::Run {
+                count = count.push(top_button("Stop", 60.0, Message::StopRecurse));
+            }
+            if paged && self.displayed.len() < self.search_results.len() {
+                count = count.push(top_button("Show all", 80.0, Message::ShowAllResults));
+            }
+            let size_info = |size: &String| if self.last_clicked.location {
+                row![Text::new(size.clone()), Text::new("  Has location data").style(color!(0xffaa33))]
+            } else {
+                row![Text::new(size.clone())]
+            };
             let ctrlbar = column![
                 row![
-                    match (&self.last_clicked.size, self.show_goto) {
-                        (Some(size), true) => row![Text::new(size),count, horizontal_space(), top_icon(self.icons.goto.clone(), Message::Goto)],
-                        (Some(size), false) => row![Text::new(size),count, horizontal_space()],
+                    match (&self.last_clicked.size, self.show_goto && !self.conf.listing()) {
+                        (Some(size), true) => row![size_info(size),count, horizontal_space(), top_icon(self.icons.goto.clone(), Message::Goto)],
+                        (Some(size), false) => row![size_info(size),count, horizontal_space()],
                         (None, true) => row![count, horizontal_space(), top_button("Goto Dir", 80.0, Message::Goto)],
                         (None, false) => row![count, horizontal_space()]
                     },
                     menu_bar![
                         (top_icon(self.icons.cmds.clone(), Message::Dummy), 
                             view_menu(cmd_list))
+                        (top_button(filter_label, 70.0, Message::Dummy),
+                            view_menu(filter_list))
                         (top_icon(self.icons.settings.clone(), Message::Dummy),
                             view_menu(menu_items!(
                                     (menu_button("Sort A-Z",Message::Sort(1)))
@@ -1479,6 +2356,7 @@ impl Application for FilePicker {
                                     (menu_button("Sort Oldest first",Message::Sort(4)))
                                     (checkbox("Show Hidden", self.show_hidden).on_toggle(Message::ShowHidden))
                                     (checkbox("Recursive Search", self.recursive_search).on_toggle(Message::SetRecursive))
+                                    (checkbox("Search File Contents", self.content_search).on_toggle(Message::SetContentSearch))
                                     (text(format!("Thumbnail size:{}", self.conf.thumb_size)))
                                     (slider(50.0..=500.0, self.conf.thumb_size, Message::Thumbsize))
                                     )))
@@ -1502,8 +2380,12 @@ impl Application for FilePicker {
                     .on_input(Message::SearchTxtInput)
                     .on_paste(Message::SearchTxtInput)
                     .width(Length::FillPortion(2)),
+                tooltip(Button::new("?").on_press(Message::Dummy).style(style::flat_but_theme()),
+                        container(text(query::HELP).font(iced::Font::MONOSPACE).size(13)).padding(8).style(Container::Box),
+                        tooltip::Position::Bottom),
                 Button::new("X").on_press(Message::SearchTxtInput("".to_string())).style(style::flat_but_theme())
-                ]
+                ].push_maybe((!self.searchbar.is_empty() && !self.conf.listing()).then(||
+                    Button::new("Save").on_press(Message::SaveSearch(false)).style(style::flat_but_theme())))
             ].align_items(iced::Alignment::End).width(Length::Fill);
             let send = clicked_offscreen || ps.total_width != size.width || ps.total_height != size.height;
             let mainview = column![
@@ -1533,6 +2415,49 @@ impl Application for FilePicker {
                     .on_esc(Message::CloseModal)
                     .align_y(alignment::Vertical::Center)
                     .into(),
+                FModal::Tags(n) => modal(mainview, Some(Card::new(
+                        text(if n == 1 { "Edit tags and description".to_string() }
+                             else { format!("Edit tags and description of {} files", n) }),
+                        column![
+                            TextInput::new(if n == 1 { "comma separated tags" } else { "comma separated tags to add" }, self.edit_tags.as_str())
+                                .on_input(Message::TagsInput)
+                                .on_paste(Message::TagsInput)
+                                .on_submit(Message::SaveTags),
+                            TextInput::new(if n == 1 { "description" } else { "description, blank keeps each file's own" }, self.edit_desc.as_str())
+                                .on_input(Message::UserDescInput)
+                                .on_paste(Message::UserDescInput)
+                                .on_submit(Message::SaveTags),
+                            row![
+                                Button::new("Save").on_press(Message::SaveTags),
+                                Button::new("Cancel").on_press(Message::CloseModal),
+                            ].spacing(5.0)
+                        ].spacing(5.0)
+                        ).max_width(500.0)
+                        .on_close(Message::CloseModal))
+                    )
+                    .backdrop(Message::CloseModal)
+                    .on_esc(Message::CloseModal)
+                    .align_y(alignment::Vertical::Center)
+                    .into(),
+                FModal::SaveSearch => modal(mainview, Some(Card::new(
+                        text("Name the saved search"),
+                        column![
+                            TextInput::new(&self.searchbar, self.save_search_name.as_str())
+                                .on_input(Message::SaveSearchInput)
+                                .on_submit(Message::SaveSearch(true))
+                                .on_paste(Message::SaveSearchInput),
+                            row![
+                                Button::new("Save").on_press(Message::SaveSearch(true)),
+                                Button::new("Cancel").on_press(Message::CloseModal),
+                            ].spacing(5.0)
+                        ]
+                        ).max_width(500.0)
+                        .on_close(Message::CloseModal))
+                    )
+                    .backdrop(Message::CloseModal)
+                    .on_esc(Message::CloseModal)
+                    .align_y(alignment::Vertical::Center)
+                    .into(),
                 FModal::NewDir => modal(mainview, Some(Card::new(
                         text("Enter new directory name"),
                         column![
@@ -1571,6 +2496,11 @@ fn top_button(txt: &str, size: f32, msg: Message) -> Element<'static, Message> {
         .style(style::top_but_theme())
         .on_press(msg).into()
 }
+/// A run of label characters, colored where the search matched
+fn label_part(txt: String, highlight: bool, shape: text::Shaping) -> Element<'static, Message> {
+    let part = text(txt).size(13).shaping(shape);
+    if highlight { part.style(color!(0xffaa33)).into() } else { part.into() }
+}
 fn top_button_off(txt: &str, size: f32) -> Element<'static, Message> {
     Button::new(text(txt)
                 .width(size)
@@ -1592,7 +2522,7 @@ impl FItem {
     #[inline]
     fn not_loaded(self: &Self) -> bool { self.thumb_handle == None && !self.path.is_empty() }
 
-    fn display(&self, last_clicked: &LastClicked, thumbsize: f32) -> (bool, Element<'static, Message>) {
+    fn display(&self, last_clicked: &LastClicked, thumbsize: f32, hit: Option<&Hit>) -> (bool, Element<'static, Message>) {
         let mut col = Column::new()
             .align_items(iced::Alignment::Center)
             .width(Length::Fixed(thumbsize));
@@ -1601,7 +2531,20 @@ impl FItem {
             _ => {},
         }
         let shape = if self.unicode { text::Shaping::Advanced } else { text::Shaping::Basic };
-        col = col.push(text(self.label.as_str()).size(13).shaping(shape));
+        match hit {
+            // placeholders of loading items have no name to highlight
+            Some(Hit::Name(indices)) if !self.path.is_empty() => col = col.push(self.highlighted_label(indices, shape)),
+            _ => col = col.push(text(self.label.as_str()).size(13).shaping(shape)),
+        }
+        match hit {
+            Some(Hit::Desc(snippet, full)) => col = col.push(tooltip(
+                text(format!("desc: {}", snippet)).size(11).style(color!(0x999999)).shaping(text::Shaping::Advanced),
+                container(text(full).size(13).shaping(text::Shaping::Advanced)).max_width(400).padding(8).style(Container::Box),
+                tooltip::Position::Bottom)),
+            Some(Hit::Line(line, snippet)) => col = col.push(
+                text(format!("{}: {}", line, snippet)).size(11).style(color!(0x999999)).shaping(text::Shaping::Advanced)),
+            _ => {},
+        }
         let idx = self.items_idx;
         let clickable = match (self.isdir(), self.sel) {
             (true, true) => {
@@ -1632,16 +2575,46 @@ impl FItem {
         }
     }
 
+    /// The label with the characters at indices of the file name colored. The label
+    /// is the end of the name, maybe after '...' and split in two lines.
+    fn highlighted_label(&self, indices: &[usize], shape: text::Shaping) -> Element<'static, Message> {
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        let flat = self.label.replace('\n', "");
+        let skip = match flat.strip_prefix("...") {
+            Some(rest) if flat.len() != name.len() && name.ends_with(rest) => 3,
+            _ => 0,
+        };
+        let offset = name.chars().count().saturating_sub(flat.chars().count() - skip);
+        let mut lines = Column::new().align_items(iced::Alignment::Center);
+        let mut k = 0;
+        for line in self.label.split('\n') {
+            let mut row = Row::new();
+            let mut part = String::new();
+            let mut part_hl = false;
+            for c in line.chars() {
+                let hl = k >= skip && indices.contains(&(offset + k - skip));
+                if hl != part_hl && !part.is_empty() {
+                    row = row.push(label_part(std::mem::take(&mut part), part_hl, shape));
+                }
+                part_hl = hl;
+                part.push(c);
+                k += 1;
+            }
+            lines = lines.push(row.push(label_part(part, part_hl, shape)));
+        }
+        lines.into()
+    }
+
     fn preview(self: &Self) -> Preview {
         if self.svg {
-            Preview::Svg(svg::Handle::from_path(&self.path))
+            Preview::Svg(svg::Handle::from_path(rawpath::to_path(&self.path)))
         } else if self.vid {
             match vid_frame(self.path.as_str(), None, None) {
                 None => Preview::None,
                 Some(a) => Preview::Image(a),
             }
         } else if self.ftype == FType::Image {
-            match std::fs::read(self.path.as_str()) {
+            match std::fs::read(rawpath::to_path(&self.path)) {
                 Ok(data) => {
                     if self.gif {
                         match iced_gif::Frames::from_bytes(data) {
@@ -1691,7 +2664,7 @@ impl FItem {
             Err(_) => (FType::NotExist, std::time::SystemTime::now(), 0),
 
         };
-        let path = pth.to_string_lossy();
+        let path = rawpath::to_str(&pth);
         let mut label = path.rsplitn(2,'/').next().unwrap().to_string();
         let hidden = label.starts_with('.');
 
@@ -1737,6 +2710,9 @@ impl FItem {
             }
         }
         let unicode = label.bytes().any(|c| c & 0b10000000 != 0);
+        let (xdg_tags, xdg_comment) = if ftype == FType::NotExist { (None, None) } else {
+            (get_xattr(&pth, "user.xdg.tags"), get_xattr(&pth, "user.xdg.comment"))
+        };
         FItem(Box::new(FItemb {
             path: path.to_string(),
             label,
@@ -1755,18 +2731,51 @@ impl FItem {
             hidden,
             recursed: false,
             unicode,
+            xdg_tags,
+            xdg_comment,
+            phash: None,
+            palette: vec![],
+            gps: None,
         }))
     }
 
+    fn set_thumb(&mut self, thumb: Option<(Handle, ThumbInfo)>) {
+        let (handle, info) = thumb.unzip();
+        self.thumb_handle = handle;
+        if let Some(info) = info {
+            self.phash = info.phash;
+            self.palette = info.palette;
+        }
+    }
+
+    /// What search_loop matches against, before it adds indexed descriptions
+    fn searchable(&self) -> FileIdx<'static> {
+        let attrs = match (&self.xdg_tags, &self.xdg_comment) {
+            (Some(t), Some(c)) => Some(format!("{} {}", t, c)),
+            (Some(t), None) => Some(t.clone()),
+            (None, Some(c)) => Some(c.clone()),
+            (None, None) => None,
+        };
+        FileIdx {
+            path: self.path.clone(),
+            text: None,
+            tags: None,
+            xattrs: attrs,
+            size: self.size,
+            mtime: self.mtime,
+            isdir: self.isdir(),
+        }
+    }
+
     async fn prepare_cached_thumbnail(
             self: &Self,
             path: &str,
             is_vid: bool,
             is_svg: bool,
             thumbsize: u32,
-            icons: Arc<Icons>) -> Option<Handle> {
+            icons: Arc<Icons>) -> Option<(Handle, ThumbInfo)> {
         let mut hasher = Md5::new();
-        let p = Path::new(path);
+        let p = rawpath::to_path(path);
         let fmetadata = p.metadata().unwrap();
         let fmtime = fmetadata.modified().unwrap().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
         let fsize = fmetadata.len();
@@ -1785,19 +2794,22 @@ impl FItem {
             file.read_to_end(&mut buffer).await.unwrap_or(0);
             let img = load_from_memory(buffer.as_ref()).unwrap();
             let (w,h,rgba) = (img.width(), img.height(), img.into_rgba8());
-            Some(Handle::from_pixels(w, h, rgba.as_raw().clone()))
+            Some((Handle::from_pixels(w, h, rgba.as_raw().clone()), ThumbInfo::new(w, h, rgba.as_raw())))
         } else if fdir.to_string_lossy() == icons.thumb_dir {
             let mut buffer = Vec::new();
-            let mut file = File::open(self.path.as_str()).await.unwrap();
+            let mut file = File::open(rawpath::to_path(&self.path)).await.unwrap();
             file.read_to_end(&mut buffer).await.unwrap_or(0);
             let img = load_from_memory(buffer.as_ref()).unwrap();
             let thumb = img.thumbnail(thumbsize, thumbsize);
             let (w,h,rgba) = (thumb.width(), thumb.height(), thumb.into_rgba8());
-            Some(Handle::from_pixels(w, h, rgba.as_raw().clone()))
+            Some((Handle::from_pixels(w, h, rgba.as_raw().clone()), ThumbInfo::new(w, h, rgba.as_raw())))
         } else if is_vid {
-            vid_frame(&path, Some(thumbsize), Some(&cache_path))
+            vid_frame(&path, Some(thumbsize), Some(&cache_path)).map(|handle| {
+                let info = ThumbInfo::from_handle(&handle);
+                (handle, info)
+            })
         } else {
-            let file = File::open(self.path.as_str()).await;
+            let file = File::open(rawpath::to_path(&self.path)).await;
             match file {
                 Ok(mut file) => {
                     let mut buffer = Vec::new();
@@ -1818,7 +2830,8 @@ impl FItem {
                                 let mut newfile = std::fs::File::create_new(cache_path).unwrap();
                                 let encoder = WebPEncoder::new_lossless(&mut newfile);
                                 encoder.encode(pixels.as_ref(), w, h, img::ExtendedColorType::Rgba8).unwrap();
-                                Some(Handle::from_pixels(w, h, pixels))
+                                let info = ThumbInfo::new(w, h, &pixels);
+                                Some((Handle::from_pixels(w, h, pixels), info))
                             },
                             Err(e) => {
                                 eprintln!("Error decoding svg {}: {}", self.path, e);
@@ -1834,7 +2847,7 @@ impl FItem {
                                 let mut newfile = std::fs::File::create_new(cache_path).unwrap();
                                 let encoder = WebPEncoder::new_lossless(&mut newfile);
                                 encoder.encode(rgba.as_ref(), w, h, img::ExtendedColorType::Rgba8).unwrap();
-                                Some(Handle::from_pixels(w, h, rgba.as_raw().clone()))
+                                Some((Handle::from_pixels(w, h, rgba.as_raw().clone()), ThumbInfo::new(w, h, rgba.as_raw())))
                             },
                             Err(e) => {
                                 eprintln!("Error decoding image {}: {}", self.path, e);
@@ -1865,7 +2878,8 @@ impl FItem {
                     let ext = ext.as_str();
                     self.ftype = match ext {
                         "svg" => {
-                            self.thumb_handle = self.prepare_cached_thumbnail(self.path.as_str(), false, true, thumbsize, icons.clone()).await;
+                            let thumb = self.prepare_cached_thumbnail(self.path.as_str(), false, true, thumbsize, icons.clone()).await;
+                            self.set_thumb(thumb);
                             if self.thumb_handle == None {
                                 self.thumb_handle = Some(icons.error.clone());
                                 FType::File
@@ -1875,7 +2889,8 @@ impl FItem {
                             }
                         },
                         "png"|"jpg"|"jpeg"|"bmp"|"tiff"|"gif"|"webp" => {
-                            self.thumb_handle = self.prepare_cached_thumbnail(self.path.as_str(), false, false, thumbsize, icons.clone()).await;
+                            let thumb = self.prepare_cached_thumbnail(self.path.as_str(), false, false, thumbsize, icons.clone()).await;
+                            self.set_thumb(thumb);
                             if self.thumb_handle == None {
                                 self.thumb_handle = Some(icons.error.clone());
                                 FType::File
@@ -1887,7 +2902,8 @@ impl FItem {
                             }
                         },
                         "webm"|"mkv"|"mp4"|"av1"|"avi"|"avif"|"flv"|"wmv"|"m4v"|"mpeg"|"mov"|"jxl" => {
-                            self.thumb_handle = self.prepare_cached_thumbnail(self.path.as_str(), true, false, thumbsize, icons.clone()).await;
+                            let thumb = self.prepare_cached_thumbnail(self.path.as_str(), true, false, thumbsize, icons.clone()).await;
+                            self.set_thumb(thumb);
                             if self.thumb_handle == None {
                                 self.thumb_handle = Some(icons.error.clone());
                                 FType::File
@@ -1916,9 +2932,15 @@ impl FItem {
     }
 }
 
+#[derive(Clone, Debug)]
 struct FileIdx<'a> {
     path: String,
     text: Option<&'a str>,
+    tags: Option<&'a str>,
+    xattrs: Option<String>,
+    size: u64,
+    mtime: f32,
+    isdir: bool,
 }
 
 enum Inochan {
@@ -1947,7 +2969,7 @@ async fn watch_inotify(mut rx: UReceiver<Inochan>, tx: USender<Inochan>) {
                         let deleted = ev.mask.contains(EventMask::DELETE);
                         match(ev.name, watches.get_mut(&ev.wd)) {
                             (Some(name),Some(dir)) => {
-                                let path = Path::new(&dir.name).join(name.clone()).to_string_lossy().to_string();
+                                let path = rawpath::to_str(rawpath::to_path(&dir.name).join(&name));
                                     if create_dir {
                                         tx.send(Inochan::Create(path)).unwrap();
                                     } else if create_file {
@@ -1971,7 +2993,7 @@ async fn watch_inotify(mut rx: UReceiver<Inochan>, tx: USender<Inochan>) {
                         watches.iter().for_each(|(wd,_)| estream.watches().remove(wd.clone()).unwrap());
                         watches.clear();
                         ls.iter().for_each(|dir|{
-                            watches.insert(estream.watches().add(dir,
+                            watches.insert(estream.watches().add(rawpath::to_path(dir),
                                                                  WatchMask::CREATE|
                                                                  WatchMask::CLOSE_WRITE|
                                                                  WatchMask::DELETE).unwrap(),
@@ -1985,6 +3007,91 @@ async fn watch_inotify(mut rx: UReceiver<Inochan>, tx: USender<Inochan>) {
     }
 }
 
+/// Tags and description typed into the edit dialog, to save for the selected files
+struct TagEdit {
+    tags: String,
+    desc: String,
+    /// Editing several files starts with blank fields, so a blank field leaves each
+    /// file's own value alone and tags are added to the ones it already has
+    multi: bool,
+    xattrs: bool,
+    db: bool,
+}
+
+/// What saving tags changed, for the UI to catch up with
+#[derive(Debug, Clone, Default)]
+struct SavedTags {
+    /// (items index, xdg tags, xdg comment)
+    xattrs: Vec<(usize, Option<String>, Option<String>)>,
+    semantics: Vec<(String,String,String)>,
+    error: Option<String>,
+}
+
+impl TagEdit {
+    fn tags(&self, old: &str) -> String {
+        let old = if self.multi { old } else { "" };
+        old.split(',').chain(self.tags.split(',')).map(str::trim).filter(|t|!t.is_empty()).unique().join(",")
+    }
+
+    fn desc(&self, old: &str) -> String {
+        if self.multi && self.desc.trim().is_empty() { old.to_string() } else { self.desc.trim().to_string() }
+    }
+
+    /// Writes the xattrs and the index off the UI thread, stopping at the first error
+    fn save(self, files: Vec<(usize, String)>) -> SavedTags {
+        let mut saved = SavedTags::default();
+        if self.xattrs {
+            for (i, path) in files.iter() {
+                let old = |name| get_xattr(&rawpath::to_path(path), name).unwrap_or_default();
+                let tags = self.tags(&old("user.xdg.tags"));
+                let comment = self.desc(&old("user.xdg.comment"));
+                let res = set_xattr(path, "user.xdg.tags", &tags)
+                    .and_then(|_|set_xattr(path, "user.xdg.comment", &comment));
+                if let Err(e) = res {
+                    saved.error = Some(format!("Error writing xattrs for {}: {}", path, e));
+                    return saved;
+                }
+                saved.xattrs.push((*i, Some(tags).filter(|t|!t.is_empty()), Some(comment).filter(|c|!c.is_empty())));
+            }
+        }
+        let Some(mut sql) = self.db.then(IndexProxy::open_db).flatten() else { return saved };
+        let res = sql.transaction().and_then(|tx| {
+            for (_, path) in files.iter() {
+                let (old_tags, old_desc) = IndexProxy::get_user_text(&tx, path);
+                match IndexProxy::set_user_text(&tx, path, &self.tags(&old_tags), &self.desc(&old_desc)) {
+                    Ok(sem) => saved.semantics.push(sem),
+                    Err(e) => {
+                        saved.error = Some(format!("Error saving tags for {}: {}", path, e));
+                        break;
+                    },
+                }
+            }
+            tx.commit()
+        });
+        if let Err(e) = res {
+            saved.error = Some(format!("Error saving tags: {}", e));
+        }
+        saved
+    }
+}
+
+fn get_xattr(path: &Path, name: &str) -> Option<String> {
+    match xattr::get_deref(path, name) {
+        Ok(Some(val)) if !val.is_empty() => Some(String::from_utf8_lossy(&val).into_owned()),
+        _ => None,
+    }
+}
+
+fn set_xattr(path: &str, name: &str, val: &str) -> std::io::Result<()> {
+    if !val.is_empty() {
+        return xattr::set_deref(rawpath::to_path(path), name, val.as_bytes());
+    }
+    match xattr::remove_deref(rawpath::to_path(path), name) {
+        Err(e) if e.raw_os_error() != Some(libc::ENODATA) => Err(e), // it was never set
+        _ => Ok(()),
+    }
+}
+
 fn shquote(s: &str) -> String {
     if s.contains("\"") {
         return format!("'{}'", s);
@@ -2026,6 +3133,34 @@ impl FilePicker {
         self.displayed.len() / maxcols + if self.displayed.len() % maxcols != 0 { 1 } else { 0 }
     }
 
+    /// Perceptual hashes of items from loaded thumbnails or index.db, if they're up to date
+    fn lookup_hashes(&self, iidxs: &[usize]) -> HashMap<usize, u64> {
+        let mut ret = HashMap::new();
+        let mut by_dir = HashMap::<&str, Vec<usize>>::new();
+        for i in iidxs {
+            let item = &self.items[*i];
+            match item.phash {
+                Some(h) => { ret.insert(*i, h); },
+                None => by_dir.entry(item.path.rsplit_once('/').map(|p|p.0).unwrap_or("")).or_default().push(*i),
+            }
+        }
+        if let Some(ref sql) = self.db {
+            for (dir, idxs) in by_dir {
+                let stored = IndexProxy::get_hashes(sql, dir);
+                for i in idxs {
+                    let item = &self.items[i];
+                    let fname = item.path.rsplit_once('/').map(|p|p.1).unwrap_or(&item.path);
+                    if let Some((mtime, h)) = stored.get(fname) {
+                        if *mtime == item.mtime {
+                            ret.insert(i, *h);
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
+
     fn run_command(self: &Self, icmd: usize) {
         let cmd = self.conf.cmds[icmd].cmd.as_str();
         self.items.iter().filter(|item| item.sel).for_each(|item| {
@@ -2049,9 +3184,10 @@ impl FilePicker {
                 .replace("[Name]", &fname)
                 .replace("[part]", &quoted_part)
                 .replace("[Part]", part);
-            let cwd = dir.to_owned();
+            let cwd = rawpath::to_path(&item.path).parent().unwrap().to_path_buf();
             eprintln!("CMD:{}", filecmd);
             tokio::task::spawn_blocking(move || {
+                let filecmd = std::ffi::OsStr::from_bytes(&rawpath::to_bytes(&filecmd)).to_owned();
                 match OsCmd::new("bash").arg("-c").arg(filecmd).current_dir(cwd).output() {
                     Ok(output) => eprintln!("{}{}",
                                             unsafe{std::str::from_utf8_unchecked(&output.stdout)},
@@ -2077,7 +3213,37 @@ impl FilePicker {
         Command::none()
     }
 
-    fn click_item(self: &mut Self, ii: usize, shift: bool, ctrl: bool, always_sel: bool) {
+    fn click_item(&mut self, ii: usize, shift: bool, ctrl: bool, always_sel: bool) -> Command<Message> {
+        self.select_item(ii, shift, ctrl, always_sel);
+        self.check_location()
+    }
+
+    /// Shows the location badge when any selected image has GPS data. Images not checked
+    /// yet have just their headers read, off the UI thread.
+    fn check_location(&mut self) -> Command<Message> {
+        let selected = self.items.iter().filter(|item| item.sel && item.ftype == FType::Image);
+        self.last_clicked.location = selected.clone().any(|item| item.gps == Some(true));
+        let unchecked = selected.filter(|item| item.gps.is_none())
+            .map(|item| (item.items_idx, item.path.clone())).collect::<Vec<_>>();
+        if unchecked.is_empty() {
+            return Command::none();
+        }
+        let nav_id = self.nav_id;
+        Command::perform(async move {
+            let mut found = Vec::with_capacity(unchecked.len());
+            for (i, path) in unchecked {
+                // EXIF sits in the first segments, which are at most 64K
+                let mut head = vec![];
+                if let Ok(file) = File::open(rawpath::to_path(&path)).await {
+                    let _ = file.take(65536).read_to_end(&mut head).await;
+                }
+                found.push((i, exif::has_gps(&head)));
+            }
+            found
+        }, move |found| Message::Location(found, nav_id))
+    }
+
+    fn select_item(self: &mut Self, ii: usize, shift: bool, ctrl: bool, always_sel: bool) {
 
         let isdir = self.items[ii].isdir();
         self.last_clicked = LastClicked{
@@ -2091,7 +3257,8 @@ impl FilePicker {
                 else if bytes > 1048576.0 { format!("{:.1}MB", bytes/1048576.0 ) }
                 else if bytes > 1024.0 { format!("{:.0}KB", bytes/1024.0 ) }
                 else { format!("{:.0}B", bytes) })
-                }
+                },
+            location: false,
         };
         let prevsel = self.items.iter().filter_map(|item| {
             if item.sel {
@@ -2100,9 +3267,10 @@ impl FilePicker {
                 None
             }
         }).collect::<Vec<usize>>();
+        let mixed = self.conf.mixed();
         while (self.conf.multi() || isdir) && shift && prevsel.len() > 0 {
             let prevdir = self.items[prevsel[0]].isdir();
-            if prevdir != isdir {
+            if prevdir != isdir && !mixed {
                 break;
             }
             let mut lo = self.items[ii].display_idx;
@@ -2113,7 +3281,7 @@ impl FilePicker {
             });
             self.show_goto = false;
             for j in lo..=hi {
-                let sel = self.items[self.displayed[j]].isdir() == isdir;
+                let sel = mixed || self.items[self.displayed[j]].isdir() == isdir;
                 let item = &mut self.items[self.displayed[j]];
                 if sel && item.recursed {
                     self.show_goto = true;
@@ -2131,7 +3299,7 @@ impl FilePicker {
         }
         let mut any_selected = false;
         prevsel.into_iter().filter(|j|*j != ii).for_each(|j| {
-            if !(ctrl && (self.conf.multi()||isdir)) || self.items[j].isdir() != isdir {
+            if !(ctrl && (self.conf.multi()||isdir)) || (self.items[j].isdir() != isdir && !mixed) {
                 self.items[j].sel = false;
             } else {
                 self.show_goto |= self.items[j].recursed || self.dirs.len() > 1;
@@ -2154,12 +3322,15 @@ impl FilePicker {
         let mut inodirs = vec![];
         self.nav_id = self.nav_id.wrapping_add(1);
         for dir in self.dirs.iter() {
-            match std::fs::read_dir(dir.as_str()) {
+            match std::fs::read_dir(rawpath::to_path(dir)) {
                 Ok(rd) => {
                     inodirs.push(dir.clone());
                     rd.map(|f| f.unwrap().path()).for_each(|path| {
+                        if self.conf.policy.as_ref().is_some_and(|p|!p.allows(&path, true)) {
+                            return;
+                        }
                         ret.push(FItem::new(path.into(), self.nav_id));
-                        if self.show_hidden || !ret.last().unwrap().hidden {
+                        if self.visible(ret.last().unwrap()) {
                             displayed.push(ret.len()-1);
                         }
                     });
@@ -2167,17 +3338,178 @@ impl FilePicker {
                 Err(e) => eprintln!("Error reading dir {}: {}", dir, e),
             }
         }
+        self.set_items(ret, displayed, inodirs);
+    }
+
+    /// Whether hidden file and filter menu settings let an item into the grid
+    fn visible(&self, item: &FItem) -> bool {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs_f32();
+        (self.show_hidden || !item.hidden)
+            && self.conf.filter().is_none_or(|f|f.allows(&item.path, item.isdir(), item.size, item.mtime, now))
+    }
+
+    /// Rebuilds the grid, or the set of searched items during a search, after visibility settings change
+    fn refresh_visible(&mut self) -> Command<Message> {
+        let end = if self.searchbar.is_empty() || !self.recursive_search { self.end_idx } else { self.items.len() };
+        let shown: Vec<bool> = self.items.iter().map(|item|self.visible(item)).collect();
+        let displayed = (0..end).filter(|i|shown[*i]).collect();
+        // a selection that can't be seen anymore shouldn't be returned
+        self.items.iter_mut().zip(shown).filter(|(_, shown)|!shown).for_each(|(item, _)|item.sel = false);
+        self.enable_sel_button = self.conf.saving() || self.items.iter().any(|item|item.sel);
+        if self.searchbar.is_empty() {
+            self.displayed = displayed;
+            self.update(Message::Sort(self.conf.sort_by()))
+        } else if self.content_search {
+            self.stop_content_search();
+            self.start_content_search();
+            Command::none()
+        } else {
+            self.update_searcher_visible(displayed);
+            Command::none()
+        }
+    }
+
+    /// Shows the image `pikeru view` was started with once it has loaded.
+    /// For a directory that's the first image in the grid.
+    fn open_pending_view(&mut self) -> Command<Message> {
+        let target = self.pending_view.as_deref().unwrap_or_default();
+        let found = if rawpath::to_path(target).is_dir() {
+            self.displayed.iter().map(|i|&self.items[*i]).find(|item|item.thumb_handle.is_none() || item.ftype == FType::Image)
+        } else {
+            // while loading, the item is a placeholder without a path
+            match self.items.iter().find(|item|item.path == target) {
+                None => return Command::none(),
+                found => found,
+            }
+        };
+        match found {
+            Some(item) if item.thumb_handle.is_none() => Command::none(),
+            Some(item) if item.ftype == FType::Image => {
+                let i = item.items_idx as i64;
+                self.pending_view = None;
+                self.update(Message::RightClick(i))
+            },
+            _ => {
+                self.pending_view = None;
+                Command::none()
+            },
+        }
+    }
+
+    /// Shows a fixed list of files instead of directory contents
+    fn load_list(&mut self, paths: Vec<String>) {
+        self.nav_id = self.nav_id.wrapping_add(1);
+        let items: Vec<FItem> = paths.into_iter().map(|path| FItem::new(path.into(), self.nav_id))
+            .filter(|item| item.ftype != FType::NotExist && self.conf.can_browse(&item.path)).collect();
+        let displayed = (0..items.len()).collect();
+        self.set_items(items, displayed, vec![]);
+    }
+
+    fn set_items(&mut self, items: Vec<FItem>, displayed: Vec<usize>, inodirs: Vec<String>) {
         self.searchbar.clear();
+        self.stop_content_search();
         self.ino_updater.as_ref().unwrap().send(Inochan::NewDirs(inodirs)).unwrap();
-        self.items = ret;
+        self.items = items;
         self.end_idx = self.items.len();
         self.items.iter_mut().enumerate().for_each(|(i,item)|item.items_idx = i);
         self.displayed = displayed;
         self.enable_sel_button = self.conf.saving();
-        self.update_searcher_items(self.items.iter().map(|item|item.path.clone()).collect());
+        self.update_searcher_items(self.items.iter().map(|item|item.searchable()).collect());
     }
 
-    fn update_searcher_items(self: &mut Self, searchable: Vec<String>) {
+    /// Greps the current dirs, or listed files, for the search text in a blocking task
+    /// whose matches come back as SearchEvent::ContentMatches
+    fn start_content_search(&mut self) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.content_cancel = Some(cancel.clone());
+        self.displayed.clear();
+        self.row_sizes.borrow_mut().reset(true);
+        let grep = content::Grep {
+            dirs: self.dirs.clone(),
+            listed: self.conf.stdin_list.clone(),
+            recursive: self.recursive_search,
+            term: self.searchbar.clone(),
+            nav_id: self.nav_id,
+            walk: self.conf.walk_opts(),
+            cancel,
+        };
+        let sender = self.content_sender.as_ref().unwrap().clone();
+        tokio::task::spawn_blocking(move || grep.run(sender));
+    }
+
+    fn stop_content_search(&mut self) {
+        if let Some(cancel) = self.content_cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        self.hits.clear();
+    }
+
+    /// Shows files a content search matched, adding the ones recursion hasn't found yet
+    fn add_content_matches(&mut self, matches: Vec<(String, usize, String)>) -> Command<Message> {
+        let mut new_items = vec![];
+        // while loading, an item is a placeholder without a path, so this is built for each batch
+        let known = {
+            let index = self.items.iter().enumerate().map(|(i, item)|(item.path.as_str(), i)).collect::<HashMap<_, _>>();
+            matches.iter().map(|(path, _, _)| index.get(path.as_str()).copied()).collect::<Vec<_>>()
+        };
+        for ((path, line, snippet), known) in matches.into_iter().zip(known) {
+            let ii = match known {
+                Some(ii) if self.visible(&self.items[ii]) => ii,
+                Some(_) => continue,
+                None => {
+                    let mut item = FItem::new(rawpath::to_path(&path).into_owned(), self.nav_id);
+                    if !self.visible(&item) {
+                        continue;
+                    }
+                    item.recursed = true;
+                    item.items_idx = self.items.len();
+                    new_items.push(item.searchable());
+                    self.items.push(item);
+                    self.items.len() - 1
+                },
+            };
+            self.items[ii].display_idx = self.displayed.len();
+            self.displayed.push(ii);
+            self.hits.insert(ii, Hit::Line(line, snippet));
+        }
+        // keep the name searcher's indexes in line with items
+        if !new_items.is_empty() {
+            self.search_commander.as_ref().unwrap().send(SearchEvent::AddItems(new_items)).unwrap();
+        }
+        self.update(Message::LoadThumbs)
+    }
+
+    /// Puts the first search_shown results in the grid and asks the searcher where the ones
+    /// without a highlight yet matched, so that's only worked out for what's shown
+    fn show_search_results(&mut self) {
+        let shown = self.search_shown.min(self.search_results.len());
+        self.displayed = self.search_results[..shown].iter().map(|(ii, _)| *ii).collect();
+        for (di, ii) in self.displayed.iter().enumerate() {
+            self.items[*ii].display_idx = di;
+        }
+        let missing = self.displayed.iter().filter(|ii| !self.hits.contains_key(ii)).copied().collect::<Vec<_>>();
+        if let (false, Some(sender)) = (missing.is_empty(), &self.search_commander) {
+            sender.send(SearchEvent::FindHits(missing, self.searchbar.clone())).unwrap();
+        }
+    }
+
+    /// Stops a recursive walk that hasn't finished and drops what it found, so the next
+    /// search walks the dirs from the start instead of leaving results half done
+    fn reset_walk(&mut self) {
+        match self.recurse_state {
+            RecState::Idle | RecState::Done => return,
+            RecState::Run => self.recurse_updater.as_ref().unwrap().send(RecMsg::Stop(self.nav_id)).unwrap(),
+            RecState::Stopped => {},
+        }
+        self.recurse_state = RecState::Idle;
+        self.dirs_scanned = 0;
+        self.items.truncate(self.end_idx);
+        self.displayed.retain(|i| *i < self.end_idx);
+        self.search_results.clear();
+        self.update_searcher_items(self.items.iter().map(|item|item.searchable()).collect());
+    }
+
+    fn update_searcher_items(self: &mut Self, searchable: Vec<FileIdx<'static>>) {
         if let Some(ref mut sender) = self.search_commander {
             sender.send(SearchEvent::NewItems(searchable, self.nav_id)).unwrap();
             sender.send(SearchEvent::NewView(self.displayed.clone())).unwrap();
@@ -2210,6 +3542,14 @@ impl FilePicker {
 
     fn exit(self: &mut Self) {
         self.conf.update(false);
+        cancel(self.conf.output, self.conf.filter(), self.conf.exit_code);
+    }
+
+    fn finish(&mut self, paths: Vec<String>) -> ! {
+        self.conf.update(false);
+        print_selection(self.conf.output, self.conf.filter(), &paths);
+        // exiting skips flushing, which null output without a trailing newline needs
+        let _ = std::io::Write::flush(&mut std::io::stdout());
         process::exit(0);
     }
 }
@@ -2321,12 +3661,192 @@ fn get_sel_theme() -> Container {
     )
 }
 
+/// Max hamming distance between perceptual hashes of images shown as duplicates
+const DUPLICATE_DISTANCE: u32 = 5;
+
+fn is_image_ext(path: &str) -> bool {
+    match path.rsplit_once('.') {
+        Some((_, ext)) => matches!(ext.to_lowercase().as_str(), "png"|"jpg"|"jpeg"|"bmp"|"tiff"|"gif"|"webp"),
+        None => false,
+    }
+}
+
+/// Difference hash: each bit says whether a cell of a 9x8 grayscale version is brighter than its right neighbor
+fn dhash(w: u32, h: u32, rgba: &[u8]) -> Option<u64> {
+    let (w, h) = (w as usize, h as usize);
+    if w == 0 || h == 0 || rgba.len() < w * h * 4 {
+        return None;
+    }
+    let mut cells = [[0.0f32; 9]; 8];
+    for (cy, row) in cells.iter_mut().enumerate() {
+        let (y0, y1) = (cy * h / 8, ((cy + 1) * h / 8).max(cy * h / 8 + 1).min(h));
+        for (cx, cell) in row.iter_mut().enumerate() {
+            let (x0, x1) = (cx * w / 9, ((cx + 1) * w / 9).max(cx * w / 9 + 1).min(w));
+            let mut sum = 0.0;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let p = &rgba[(y * w + x) * 4..];
+                    sum += 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+                }
+            }
+            *cell = sum / ((y1 - y0) * (x1 - x0)) as f32;
+        }
+    }
+    let mut hash = 0u64;
+    for row in cells.iter() {
+        for x in 0..8 {
+            hash = (hash << 1) | (row[x] > row[x + 1]) as u64;
+        }
+    }
+    Some(hash)
+}
+
+/// Most common colors, quantized to 4 bits per channel and skipping ones that look alike
+fn palette(rgba: &[u8]) -> Vec<[u8; 3]> {
+    let mut bins = vec![(0u32, [0u32; 3]); 4096];
+    for p in rgba.chunks_exact(4).filter(|p|p[3] >= 128) {
+        let bin = &mut bins[(p[0] as usize >> 4) << 8 | (p[1] as usize >> 4) << 4 | p[2] as usize >> 4];
+        bin.0 += 1;
+        (0..3).for_each(|c| bin.1[c] += p[c] as u32);
+    }
+    bins.sort_by_key(|b| std::cmp::Reverse(b.0));
+    let mut ret: Vec<[u8; 3]> = vec![];
+    for (count, sum) in bins.into_iter().take_while(|b|b.0 > 0) {
+        let color = [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8];
+        if ret.iter().all(|c|delta_e(lab(*c), lab(color)) > 12.0) {
+            ret.push(color);
+            if ret.len() == 5 {
+                break;
+            }
+        }
+    }
+    ret
+}
+
+/// CIELAB coordinates of an sRGB color, where distances roughly match perceived differences
+fn lab(c: [u8; 3]) -> [f32; 3] {
+    let lin = c.map(|v| {
+        let v = v as f32 / 255.0;
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    });
+    let x = (0.4124 * lin[0] + 0.3576 * lin[1] + 0.1805 * lin[2]) / 0.95047;
+    let y = 0.2126 * lin[0] + 0.7152 * lin[1] + 0.0722 * lin[2];
+    let z = (0.0193 * lin[0] + 0.1192 * lin[1] + 0.9505 * lin[2]) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn delta_e(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Parses `#rgb`, `#rrggbb` or a basic color name
+fn parse_color(s: &str) -> Option<[u8; 3]> {
+    if let Some(hex) = s.strip_prefix('#') {
+        let v = u32::from_str_radix(hex, 16).ok()?;
+        return match hex.len() {
+            3 => Some([((v >> 8) & 0xf) as u8 * 17, ((v >> 4) & 0xf) as u8 * 17, (v & 0xf) as u8 * 17]),
+            6 => Some([(v >> 16) as u8, (v >> 8) as u8, v as u8]),
+            _ => None,
+        };
+    }
+    let rgb = match s.to_lowercase().as_str() {
+        "black" => 0x000000, "white" => 0xffffff, "gray"|"grey" => 0x808080, "silver" => 0xc0c0c0,
+        "red" => 0xff0000, "maroon" => 0x800000, "orange" => 0xffa500, "yellow" => 0xffff00,
+        "gold" => 0xffd700, "olive" => 0x808000, "lime" => 0x00ff00, "green" => 0x008000,
+        "teal" => 0x008080, "cyan"|"aqua" => 0x00ffff, "turquoise" => 0x40e0d0, "blue" => 0x0000ff,
+        "navy" => 0x000080, "purple" => 0x800080, "violet" => 0xee82ee, "indigo" => 0x4b0082,
+        "magenta"|"fuchsia" => 0xff00ff, "pink" => 0xffc0cb, "brown" => 0xa52a2a, "beige" => 0xf5f5dc,
+        _ => return None,
+    };
+    Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
+
+/// Features computed from thumbnail pixels and saved to index.db
+#[derive(Debug, Clone, Default)]
+struct ThumbInfo {
+    /// None when the pixels couldn't be hashed
+    phash: Option<u64>,
+    palette: Vec<[u8; 3]>,
+}
+impl ThumbInfo {
+    fn new(w: u32, h: u32, rgba: &[u8]) -> Self {
+        Self {
+            phash: dhash(w, h, rgba),
+            palette: palette(rgba),
+        }
+    }
+
+    fn from_handle(handle: &Handle) -> Self {
+        match handle.data() {
+            iced::advanced::image::Data::Rgba { width, height, pixels } => Self::new(*width, *height, pixels),
+            _ => Self::default(),
+        }
+    }
+}
+
+/// An image for find_duplicates, with its hash if one was already known
+struct DupImage {
+    idx: usize,
+    path: String,
+    mtime: f32,
+    phash: Option<u64>,
+}
+
+/// Hashes the images that have no hash yet, saving them to index.db when save is set,
+/// and returns the images that look alike grouped together in path order
+fn find_duplicates(mut images: Vec<DupImage>, thumbsize: u32, save: bool) -> Vec<usize> {
+    let computed = images.iter_mut().filter(|image|image.phash.is_none()).filter_map(|image| {
+        let info = file_thumbinfo(&image.path, thumbsize)?;
+        image.phash = info.phash;
+        Some((image.path.clone(), image.mtime, info))
+    }).collect::<Vec<_>>();
+    if let Some(mut sql) = save.then(IndexProxy::open_db).flatten() {
+        let res = sql.transaction().and_then(|tx| {
+            computed.iter().for_each(|(path, mtime, info)| IndexProxy::save_thumbinfo(&tx, path, *mtime, info));
+            tx.commit()
+        });
+        if let Err(e) = res {
+            eprintln!("Error saving image hashes: {}", e);
+        }
+    }
+    let mut hashes = images.into_iter().filter_map(|image|Some((image.idx, image.path, image.phash?))).collect::<Vec<_>>();
+    hashes.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut grouped = vec![false; hashes.len()];
+    let mut displayed = vec![];
+    for a in 0..hashes.len() {
+        if grouped[a] {
+            continue;
+        }
+        let mut group = vec![hashes[a].0];
+        for b in a+1..hashes.len() {
+            if !grouped[b] && (hashes[a].2 ^ hashes[b].2).count_ones() <= DUPLICATE_DISTANCE {
+                grouped[b] = true;
+                group.push(hashes[b].0);
+            }
+        }
+        if group.len() > 1 {
+            displayed.append(&mut group);
+        }
+    }
+    displayed
+}
+
+/// Computes info for an image that hasn't been thumbnailed yet, the same way prepare_cached_thumbnail would
+fn file_thumbinfo(path: &str, thumbsize: u32) -> Option<ThumbInfo> {
+    let data = std::fs::read(rawpath::to_path(path)).ok()?;
+    let thumb = load_from_memory(data.as_ref()).ok()?.thumbnail(thumbsize, thumbsize);
+    let (w, h, rgba) = (thumb.width(), thumb.height(), thumb.into_rgba8());
+    Some(ThumbInfo::new(w, h, rgba.as_raw()))
+}
+
 fn vid_frame(src: &str, thumbnail: Option<u32>, savepath: Option<&PathBuf>) -> Option<Handle> {
     let mut decoder = if let Some(thumbsize) = thumbnail {
-        DecoderBuilder::new(Location::File(src.into()))
+        DecoderBuilder::new(Location::File(rawpath::to_path(src).into_owned()))
             .with_resize(Resize::Fit(thumbsize, thumbsize)).build().ok()?
     } else {
-        Decoder::new(Location::File(src.into())).ok()?
+        Decoder::new(Location::File(rawpath::to_path(src).into_owned())).ok()?
     };
     let (w, h) = decoder.size_out();
     let decoded = decoder.decode_iter().next()?;
@@ -2355,38 +3875,129 @@ fn vid_frame(src: &str, thumbnail: Option<u32>, savepath: Option<&PathBuf>) -> O
     }
 }
 
+/// Walks dirs like a recursive search in the picker and prints the files matching term, best first
+fn search(conf: &Config, dirs: &[String], term: &str) -> ! {
+    let mut files = vec![];
+    conf.walk_opts().walk(dirs, true, &mut |path| {
+        let item = FItem::new(path, 0);
+        if !item.hidden {
+            files.push(item.searchable());
+        }
+        true
+    });
+    let walked = files.iter().filter_map(|f|Path::new(&f.path).parent()).map(|p|p.to_string_lossy().to_string()).unique().collect::<Vec<_>>();
+    let mut semantics = HashMap::new();
+    let mut palettes = HashMap::new();
+    if let Some(sql) = IndexProxy::open_db() {
+        // stay under sqlite's limit on query parameters
+        for chunk in walked.chunks(500) {
+            let (sem, pal) = IndexProxy::get_semantics(&sql, &chunk.iter().map(String::as_str).collect::<Vec<_>>());
+            semantics.extend(sem.into_iter().map(|(path, text, tags)|(path, (text, tags))));
+            palettes.extend(pal.into_iter().map(|(path, colors)|(path, colors.into_iter().map(lab).collect::<Vec<_>>())));
+        }
+    }
+    let query = Query::new(term);
+    let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
+    let mut results = files.into_iter().filter_map(|file: FileIdx| {
+        let (text, tags) = semantics.get(&file.path).map(|(text, tags): &(String, String)|
+            (Some(text.as_str()).filter(|t|!t.is_empty()), Some(tags.as_str()).filter(|t|!t.is_empty()))).unwrap_or_default();
+        let item = FileIdx { path: file.path, text, tags, xattrs: file.xattrs, size: file.size, mtime: file.mtime, isdir: file.isdir };
+        let score = query.score(&matcher, &item, palettes.get(&item.path))?;
+        Some((score, item))
+    }).collect::<Vec<_>>();
+    results.sort_by(|a, b|b.0.cmp(&a.0).then_with(|| a.1.path.cmp(&b.1.path)));
+    match conf.output {
+        Output::Lines => results.iter().for_each(|(score, item)| {
+            print!("{}\t", score);
+            print_path(&item.path, "\n");
+        }),
+        Output::Null => results.iter().for_each(|(_, item)|print_path(&item.path, "\0")),
+        Output::Uri => results.iter().for_each(|(_, item)|println!("{}", uri::file_uri(&rawpath::to_path(&item.path)))),
+        Output::Json => {
+            let opt = |s: Option<&str>| s.map_or("null".to_string(), json_str);
+            let files = results.iter().map(|(score, item)|format!("{{\"path\":{},\"score\":{},\"description\":{},\"tags\":{}}}",
+                json_str(&rawpath::to_path(&item.path).to_string_lossy()), score, opt(item.text), opt(item.tags))).join(",");
+            println!("{{\"query\":{},\"results\":[{}]}}", json_str(term), files);
+        },
+    }
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    process::exit(if conf.exit_code && results.is_empty() { 1 } else { 0 });
+}
+
+/// Walks dirs like the picker's content search and prints the first line matching term in each text file
+fn grep(conf: &Config, dirs: &[String], term: &str) -> ! {
+    let (sender, mut receiver) = unbounded_channel();
+    content::Grep {
+        dirs: dirs.to_vec(),
+        listed: None,
+        recursive: true,
+        term: term.to_string(),
+        nav_id: 0,
+        walk: conf.walk_opts(),
+        cancel: Default::default(),
+    }.run(sender);
+    let mut results = vec![];
+    while let Ok(SearchEvent::ContentMatches(mut matches, _, _)) = receiver.try_recv() {
+        results.append(&mut matches);
+    }
+    results.retain(|(path, _, _)| !Path::new(path).file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')));
+    results.sort();
+    match conf.output {
+        Output::Lines => results.iter().for_each(|(path, line, snippet)| {
+            print_path(path, "");
+            println!(":{}:{}", line, snippet);
+        }),
+        Output::Null => results.iter().for_each(|(path, _, _)|print_path(path, "\0")),
+        Output::Uri => results.iter().for_each(|(path, _, _)|println!("{}", uri::file_uri(&rawpath::to_path(path)))),
+        Output::Json => {
+            let files = results.iter().map(|(path, line, snippet)|format!("{{\"path\":{},\"line\":{},\"snippet\":{}}}",
+                json_str(&rawpath::to_path(path).to_string_lossy()), line, json_str(snippet))).join(",");
+            println!("{{\"query\":{},\"results\":[{}]}}", json_str(term), files);
+        },
+    }
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    process::exit(if conf.exit_code && results.is_empty() { 1 } else { 0 });
+}
+
 async fn search_loop(mut commands: UReceiver<SearchEvent>,
                      result_sender: USender<SearchEvent>) {
     let mut items = vec![];
     let mut displayed = vec![];
     let mut nav_id = 0;
-    let semantics = RefCell::new(HashMap::<String,&'static str>::new());
+    let semantics = RefCell::new(HashMap::<String,(&'static str, &'static str)>::new());
+    let mut palettes = HashMap::<String, Vec<[f32; 3]>>::new();
     let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
+    // the last term searched and how much of displayed it covered, so when only more
+    // items have come in since, just those are searched and sent
+    let mut searched: Option<(String, usize)> = None;
+    let mut palettes_changed = false;
     loop {
         match commands.recv().await {
             Some(SearchEvent::NewItems(paths, nid)) => {
-                items = paths.into_iter().map(|path| {
-                    nav_id = nid;
-                    let sem = semantics.borrow();
-                    let text = sem.get(&path).copied();
-                    FileIdx {
-                        path,
-                        text,
-                    }
+                searched = None;
+                nav_id = nid;
+                let sem = semantics.borrow();
+                items = paths.into_iter().map(|mut item| {
+                    (item.text, item.tags) = sem.get(&item.path).copied().unzip();
+                    item
                 }).collect();
             },
             Some(SearchEvent::AddItems(paths)) => {
                 let sem = semantics.borrow();
-                let mut new_items = paths.into_iter().map(|path| {
-                    let text = sem.get(&path).copied();
-                    FileIdx {
-                        path,
-                        text,
-                    }
+                let mut new_items = paths.into_iter().map(|mut item| {
+                    (item.text, item.tags) = sem.get(&item.path).copied().unzip();
+                    item
                 }).collect::<Vec<FileIdx<'_>>>();
                 items.append(&mut new_items);
             },
+            Some(SearchEvent::SetAttrs(attrs)) => {
+                searched = None;
+                attrs.into_iter().for_each(|(i, xattrs)| if let Some(item) = items.get_mut(i) {
+                    item.xattrs = xattrs;
+                });
+            },
             Some(SearchEvent::NewView(didxs)) => {
+                searched = None;
                 displayed = didxs;
             }
             Some(SearchEvent::AddView(mut didxs)) => {
@@ -2394,34 +4005,52 @@ async fn search_loop(mut commands: UReceiver<SearchEvent>,
             }
             Some(SearchEvent::AddSemantics(new_sem)) => {
                 let mut sem = semantics.borrow_mut();
-                new_sem.into_iter().for_each(|ps|{sem.insert(ps.0, Box::leak(ps.1.into_boxed_str()));});
+                let mut changed = HashSet::new();
+                new_sem.into_iter().for_each(|ps|{
+                    changed.insert(ps.0.clone());
+                    sem.insert(ps.0, (Box::leak(ps.1.into_boxed_str()), Box::leak(ps.2.into_boxed_str())));
+                });
                 items.iter_mut().for_each(|item|{
-                    if item.text == None {
-                        item.text = sem.get(&item.path).copied();
+                    if changed.contains(&item.path) {
+                        (item.text, item.tags) = sem.get(&item.path).copied().unzip();
+                        searched = None;
                     }
                 });
             },
-            Some(SearchEvent::Search(term)) => {
-                let mut results = displayed.iter().filter_map(|i| {
+            Some(SearchEvent::AddPalettes(new_palettes)) => {
+                new_palettes.into_iter().for_each(|(path, colors)| {
+                    palettes.insert(path, colors.into_iter().map(lab).collect());
+                });
+                palettes_changed = true;
+            },
+            Some(ev @ (SearchEvent::Search(_) | SearchEvent::SearchMore(_))) => {
+                let more = matches!(ev, SearchEvent::SearchMore(_));
+                let (SearchEvent::Search(term) | SearchEvent::SearchMore(term)) = ev else { unreachable!() };
+                let query = Query::new(&term);
+                // palettes come in for files already searched, so color searches are redone
+                let from = match searched {
+                    Some((ref prev, n)) if more && *prev == term && n <= displayed.len()
+                        && !(palettes_changed && query.has_colors()) => n,
+                    _ => 0,
+                };
+                palettes_changed = false;
+                let mut results = displayed[from..].iter().filter_map(|i| {
                     let item = &items[*i];
-                    let path = Path::new(item.path.as_str());
-                    let name_match = matcher.fuzzy_match(match path.file_name() {
-                        Some(p) => p.to_string_lossy(),
-                        None => path.to_string_lossy(),
-                    }.as_ref(), term.as_str());
-                    let sem_match = match item.text {
-                        Some(text) => matcher.fuzzy_match(text, term.as_str()),
-                        None => None,
-                    };
-                    match (name_match, sem_match) {
-                        (Some(a), Some(b)) => Some((*i, a.max(b))),
-                        (Some(a), None) => Some((*i, a)),
-                        (None, Some(b)) => Some((*i, b)),
-                        (None, None) => None,
-                    }
+                    Some((*i, query.score(&matcher, item, palettes.get(&item.path))?))
                 }).collect::<Vec<_>>();
                 results.sort_by(|a,b|b.1.cmp(&a.1));
-                result_sender.send(SearchEvent::Results(results, nav_id, items.len(), term)).unwrap();
+                let num_items = items.len();
+                result_sender.send(if from == 0 {
+                    SearchEvent::Results(results, nav_id, num_items, term.clone())
+                } else {
+                    SearchEvent::MoreResults(results, nav_id, num_items, term.clone())
+                }).unwrap();
+                searched = Some((term, displayed.len()));
+            },
+            Some(SearchEvent::FindHits(idxs, term)) => {
+                let query = Query::new(&term);
+                let hits = idxs.into_iter().filter_map(|i| Some((i, query.hit(&matcher, items.get(i)?)?))).collect();
+                result_sender.send(SearchEvent::Hits(hits, nav_id, term)).unwrap();
             },
             _ => unreachable!(),
         }
@@ -2430,89 +4059,137 @@ async fn search_loop(mut commands: UReceiver<SearchEvent>,
 
 enum RecMsg {
     NewNav(Vec::<String>, u8),
-    FetchMore(u8, bool),
+    /// walks everything below the current dirs
+    Start(u8),
+    Stop(u8),
+    /// items and the dirs they're in from walker threads, and how many dirs were scanned
+    Found(Vec<FItem>, Vec<String>, usize, u8),
     NextItems(Vec<FItem>, u8),
-    SetRecursive(bool),
+    /// dirs scanned so far and whether the walk has ended
+    Progress(usize, bool, u8),
+}
+
+/// What one walker thread found since it last sent to recursive_add
+struct WalkBatch {
+    items: Vec<FItem>,
+    dirs: Vec<String>,
+    scanned: Arc<AtomicUsize>,
+    sender: USender<RecMsg>,
+    nav_id: u8,
+    last_send: Instant,
+    cancel: Arc<AtomicBool>,
+}
+
+impl WalkBatch {
+    /// Nothing goes out once the walk is stopped, so a walk started over doesn't get this one's leftovers
+    fn send(&mut self) {
+        if self.cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        if !self.items.is_empty() || !self.dirs.is_empty() {
+            let _ = self.sender.send(RecMsg::Found(mem::take(&mut self.items), mem::take(&mut self.dirs),
+                                                   self.scanned.load(Ordering::Relaxed), self.nav_id));
+        }
+        self.last_send = Instant::now();
+    }
+}
+
+impl Drop for WalkBatch {
+    /// the walk drops each thread's visitor when it's done, so the rest goes out here
+    fn drop(&mut self) {
+        self.send();
+    }
 }
 
 async fn recursive_add(mut updates: UReceiver<RecMsg>,
                        results: USender<RecMsg>,
                        selfy: USender<RecMsg>,
                        semchan: USender<SearchEvent>,
-                       gitignore_txt: String,
-                       respect_gitignore: bool) {
+                       walk: WalkOpts) {
     let mut nav_id = 0;
-    let mut recursive = true;
     let mut dirs = vec![];
-    let mut ignores: Vec<Vec<Arc<gitignore::Gitignore>>> = vec![];
+    let mut cancel = Arc::new(AtomicBool::new(false));
     let mut indexer = IndexProxy::new().await;
-    indexer.configure(respect_gitignore, gitignore_txt.as_str()).await;
-    let mut ig_builder = gitignore::GitignoreBuilder::new("");
-    gitignore_txt.lines().for_each(|line|{ig_builder.add_line(None, line).unwrap();});
-    let top_ignore = Arc::new(ig_builder.build().unwrap());
+    indexer.configure(walk.respect_gitignore, walk.gitignore.as_str()).await;
     loop {
         match updates.recv().await {
-            Some(RecMsg::SetRecursive(rec)) => {
-                recursive = rec;
-            },
             Some(RecMsg::NewNav(new_dirs, nid)) => {
+                cancel.store(true, Ordering::Relaxed);
                 dirs = new_dirs;
-                ignores = dirs.iter().map(|_|vec![top_ignore.clone()]).collect();
                 nav_id = nid;
-                selfy.send(RecMsg::FetchMore(nid, false)).unwrap();
+                send_semantics(&mut indexer, &semchan, &dirs).await;
             }
-            Some(RecMsg::FetchMore(nid, get_items)) => {
-                if nid != nav_id {
-                    continue;
-                }
-                let mut new_items = vec![];
-                let mut next_dirs = vec![];
-                let mut next_ignores = vec![];
-                let semantics = indexer.update(&dirs).await;
-                if !semantics.is_empty() {
-                    semchan.send(SearchEvent::AddSemantics(semantics)).unwrap();
-                }
-                if !recursive {
-                    continue;
-                }
-                for (i, dir) in dirs.iter().enumerate() {
-                    match std::fs::read_dir(dir.as_str()) {
-                        Ok(rd) => {
-                            if respect_gitignore {
-                                let local_ignore = Path::new(&dir).join(".gitignore");
-                                if local_ignore.is_file() {
-                                    ignores[i].push(Arc::new(gitignore::Gitignore::new(local_ignore).0));
-                                }
+            Some(RecMsg::Start(nid)) if nid == nav_id => {
+                cancel.store(true, Ordering::Relaxed);
+                cancel = Arc::new(AtomicBool::new(false));
+                let Some(walker) = walk.parallel(&dirs) else { continue };
+                let (cancel, selfy) = (cancel.clone(), selfy.clone());
+                tokio::task::spawn_blocking(move || {
+                    let scanned = Arc::new(AtomicUsize::new(0));
+                    walker.run(|| {
+                        let cancel = cancel.clone();
+                        let mut batch = WalkBatch { items: vec![], dirs: vec![], scanned: scanned.clone(), sender: selfy.clone(),
+                                                    nav_id: nid, last_send: Instant::now(), cancel: cancel.clone() };
+                        Box::new(move |entry| {
+                            if cancel.load(Ordering::Relaxed) {
+                                return WalkState::Quit;
                             }
-                            rd.map(|f| f.unwrap().path()).for_each(|path| {
-                                let ignored = ignores[i].iter().any(|g|match g.matched(&path, path.is_dir()) {
-                                    Match::Ignore(_) => true,
-                                    _ => false,
-                                });
-                                if ignored {
-                                    return;
-                                }
-                                if path.is_dir() {
-                                    next_dirs.push(path.to_string_lossy().to_string());
-                                    next_ignores.push(ignores[i].clone());
-                                }
-                                if get_items {
-                                    let mut item = FItem::new(path.into(), nid);
-                                    item.recursed = true;
-                                    new_items.push(item);
+                            let entry = match entry {
+                                Ok(entry) => entry,
+                                Err(e) => {
+                                    eprintln!("Error reading dir: {}", e);
+                                    return WalkState::Continue;
+                                },
+                            };
+                            if entry.file_type().is_some_and(|t|t.is_dir()) {
+                                batch.scanned.fetch_add(1, Ordering::Relaxed);
+                                if entry.depth() > 0 {
+                                    batch.dirs.push(rawpath::to_str(entry.path()));
                                 }
-                            });
-                        },
-                        Err(e) => eprintln!("Error reading dir {}: {}", dir, e),
+                            }
+                            // the dirs' own entries are already in the grid
+                            if entry.depth() > 1 {
+                                let mut item = FItem::new(entry.into_path(), nid);
+                                item.recursed = true;
+                                batch.items.push(item);
+                            }
+                            if batch.items.len() >= 500 || batch.last_send.elapsed() > Duration::from_millis(100) {
+                                batch.send();
+                            }
+                            WalkState::Continue
+                        })
+                    });
+                    if !cancel.load(Ordering::Relaxed) {
+                        let _ = selfy.send(RecMsg::Progress(scanned.load(Ordering::Relaxed), true, nid));
                     }
+                });
+            },
+            Some(RecMsg::Stop(nid)) if nid == nav_id => cancel.store(true, Ordering::Relaxed),
+            Some(RecMsg::Found(items, found_dirs, scanned, nid)) if nid == nav_id && !cancel.load(Ordering::Relaxed) => {
+                send_semantics(&mut indexer, &semchan, &found_dirs).await;
+                if !items.is_empty() {
+                    results.send(RecMsg::NextItems(items, nid)).unwrap();
                 }
-                dirs = next_dirs;
-                ignores = next_ignores;
-                if !new_items.is_empty() {
-                    results.send(RecMsg::NextItems(new_items, nid)).unwrap();
-                }
+                results.send(RecMsg::Progress(scanned, false, nid)).unwrap();
+            },
+            Some(RecMsg::Progress(scanned, done, nid)) if nid == nav_id && !cancel.load(Ordering::Relaxed) => {
+                results.send(RecMsg::Progress(scanned, done, nid)).unwrap();
             },
             _ => {},
         };
     }
 }
+
+/// Has the indexer look at dirs and sends what it already knows about their files to the search loop
+async fn send_semantics(indexer: &mut IndexProxy<'_>, semchan: &USender<SearchEvent>, dirs: &[String]) {
+    // stay under sqlite's limit on query parameters
+    for chunk in dirs.chunks(500) {
+        let (semantics, palettes) = indexer.update(chunk).await;
+        if !semantics.is_empty() {
+            semchan.send(SearchEvent::AddSemantics(semantics)).unwrap();
+        }
+        if !palettes.is_empty() {
+            semchan.send(SearchEvent::AddPalettes(palettes)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// Access limits the portal sets per application. The portal checks returned paths
+// against them and the picker hides whatever they rule out.
+use std::path::{Path, PathBuf};
+use ignore::gitignore;
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    roots: Vec<PathBuf>,
+    deny: gitignore::Gitignore,
+    max_size: Option<u64>,
+}
+
+impl Policy {
+    /// deny takes gitignore patterns matched against absolute paths, like /home/me/.ssh or *.pem
+    pub fn new(roots: &[impl AsRef<Path>], deny: &[String], max_size: Option<u64>) -> Self {
+        let mut builder = gitignore::GitignoreBuilder::new("/");
+        for glob in deny {
+            if let Err(e) = builder.add_line(None, glob) {
+                eprintln!("Bad deny pattern {}: {}", glob, e);
+            }
+        }
+        Self {
+            roots: roots.iter().map(|root| std::fs::canonicalize(root).unwrap_or(root.as_ref().to_path_buf())).collect(),
+            deny: builder.build().unwrap_or(gitignore::Gitignore::empty()),
+            max_size,
+        }
+    }
+
+    /// Where to start browsing when the requested path is off limits
+    pub fn start_dir(&self) -> Option<&Path> {
+        self.roots.first().map(|root| root.as_path())
+    }
+
+    /// When browsing, directories above the allowed roots pass too so they can be navigated through.
+    pub fn allows(&self, path: &Path, browsing: bool) -> bool {
+        let real = resolve(path);
+        if !real.is_absolute() {
+            return false;
+        }
+        let meta = std::fs::metadata(&real);
+        let isdir = meta.as_ref().is_ok_and(|m| m.is_dir());
+        if self.deny.matched_path_or_any_parents(&real, isdir).is_ignore() {
+            return false;
+        }
+        if !self.roots.is_empty() && !self.roots.iter().any(|root|
+                real.starts_with(root) || (browsing && isdir && root.starts_with(&real))) {
+            return false;
+        }
+        match (self.max_size, meta) {
+            (Some(max), Ok(m)) if m.is_file() => m.len() <= max,
+            _ => true,
+        }
+    }
+}
+
+/// Follows symlinks so a link can't point around the policy. Paths that
+/// don't exist yet, like save targets, are resolved through their parent.
+fn resolve(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => std::fs::canonicalize(dir).map(|d| d.join(name)).unwrap_or(path.to_path_buf()),
+        _ => path.to_path_buf(),
+    })
+}
+
+/// Parses sizes like 500K, 20M or 1G into bytes
+pub fn parse_size(txt: &str) -> Option<u64> {
+    let txt = txt.trim().to_ascii_uppercase();
+    let txt = txt.trim_end_matches('B');
+    let (num, mult) = match txt.chars().last()? {
+        'K' => (&txt[..txt.len()-1], 1 << 10),
+        'M' => (&txt[..txt.len()-1], 1 << 20),
+        'G' => (&txt[..txt.len()-1], 1 << 30),
+        _ => (txt, 1),
+    };
+    num.trim().parse::<f64>().ok().map(|n| (n * mult as f64) as u64)
+}
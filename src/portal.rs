@@ -3,17 +3,19 @@
 use getopts::Options;
 use zbus::{
     connection, interface,
-    zvariant::{Value,OwnedValue,ObjectPath,
-    to_bytes,LE,serialized::Context
-    }
+    zvariant::{Value,OwnedValue,ObjectPath}
 };
 use std::{
     error::Error, future::pending,
     collections::HashMap,
     borrow::Cow,
-    path::Path,
+    path::{Path,PathBuf},
+    process::Stdio,
+    ffi::{OsStr,OsString},
+    os::unix::{ffi::{OsStrExt,OsStringExt}, process::ExitStatusExt},
     mem::take,
-    sync::{Arc,Mutex},
+    sync::{Arc,Mutex,RwLock},
+    time::{SystemTime,UNIX_EPOCH},
 };
 use rusqlite;
 use tokio::{
@@ -25,12 +27,23 @@ use tokio::{
     time,
     time::sleep,
     time::Duration,
+    signal::unix::{signal, SignalKind},
+    io::{AsyncRead, AsyncReadExt},
 
 };
 use log::{info,trace,error,debug,warn,LevelFilter};
 use env_logger::Builder;
-use ctrlc;
+use inotify::{Inotify, WatchMask};
 use ignore::{gitignore,Match};
+use itertools::Itertools;
+use video_rs::{DecoderBuilder, Location, Resize};
+mod policy;
+use policy::{Policy, parse_size};
+mod postprocess;
+mod exif;
+mod uri;
+use uri::file_uri;
+mod rawpath;
 
 
 #[derive(Default, Debug)]
@@ -44,25 +57,35 @@ enum Msg {
     Start,
     Dirs(Vec<String>),
     Ignore(String),
+    Reload(Box<Config>),
 }
 
 struct IdxManager {
     shtate: Arc<Mutex<Shtate>>,
     cmd: String,
     check: String,
-    exts: Vec<&'static str>,
+    exts: Vec<String>,
+    video_exts: Vec<String>,
+    video_frames: usize,
     con: Arc<Mutex<rusqlite::Connection>>,
     ignore: gitignore::Gitignore,
     igtxt: String,
 }
 
-async fn index_loop(mut mgr: IdxManager, mut chan: UReceiver<Msg>, enabled: bool) {
+async fn index_loop(mut mgr: IdxManager, mut chan: UReceiver<Msg>, mut enabled: bool) {
     let mut done_map = HashMap::<String,bool>::new();
     let mut timeout = time::Instant::now().checked_add(time::Duration::from_secs(60)).unwrap();
     let mut online = mgr.indexer_online().await;
     info!("indexer {}", if online {"online"} else {"offline"});
     loop {
         let msg = chan.recv().await.unwrap();
+        if let Msg::Reload(mut config) = msg {
+            mgr.apply(&mut config);
+            enabled = config.indexer_enabled;
+            // check the new indexer on the next message
+            timeout = time::Instant::now();
+            continue;
+        }
         if !enabled { continue; }
         let uptodate = timeout.cmp(&time::Instant::now()) == core::cmp::Ordering::Greater;
         if !uptodate {
@@ -102,11 +125,21 @@ async fn index_loop(mut mgr: IdxManager, mut chan: UReceiver<Msg>, enabled: bool
             },
             Msg::Ignore(txt) => {
                 mgr.update_ignore(txt);
-            }
+            },
+            Msg::Reload(_) => {},
         }
     }
 }
 
+/// Reads a child's output to the end, so it can't block on a full pipe while being waited on
+async fn read_pipe<R: AsyncRead + Unpin>(pipe: Option<R>) -> Vec<u8> {
+    let mut buf = vec![];
+    if let Some(mut pipe) = pipe {
+        let _ = pipe.read_to_end(&mut buf).await;
+    }
+    buf
+}
+
 fn shquote(s: &str) -> String {
     if s.contains("\"") {
         return format!("'{}'", s);
@@ -129,28 +162,40 @@ impl IdxManager {
         match con.lock() {
            Ok(c) => { 
             c.execute("create table if not exists descriptions
-                      (fname text, dir text, description text, mtime real);", ()).unwrap();
+                      (fname text, dir text, description text, mtime real,
+                      tags text, user_description text);", ()).unwrap();
+            // tags and user_description are written by the picker and never touched by indexing.
+            // Older databases don't have them yet, so errors from existing columns are expected here.
+            for col in ["tags", "user_description"] {
+                let _ = c.execute(format!("alter table descriptions add column {} text", col).as_str(), ());
+            }
             c.pragma_update(None, "journal_mode", "WAL").unwrap();
            },
            Err(e) => eprintln!("{}", e),
         }
-        let con2 = con.clone();
-        ctrlc::set_handler(move || {
-            if let Err(e) = con2.lock().unwrap().cache_flush() {
-                eprintln!("failed to flush index db:{}", e);
-            }
-            eprintln!("Portal closing");
-            std::process::exit(0);
-        }).expect("Error setting Ctrl-C handler");
-        Self {
+        let mut mgr = Self {
             shtate,
-            cmd: take(&mut config.indexer_cmd),
-            check: take(&mut config.indexer_check),
-            exts: Box::new(take(&mut config.indexer_exts)).leak().split(',').collect(),
+            cmd: String::new(),
+            check: String::new(),
+            exts: vec![],
+            video_exts: vec![],
+            video_frames: 0,
             con,
             ignore: gitignore::Gitignore::new("").0,
             igtxt: String::new(),
-        }
+        };
+        mgr.apply(config);
+        mgr
+    }
+
+    /// Takes the settings that can change when the config is reloaded
+    fn apply(&mut self, config: &mut Config) {
+        let ext_list = |exts: &str| exts.split(',').map(|e|e.trim().to_ascii_lowercase()).filter(|e|!e.is_empty()).collect();
+        self.cmd = take(&mut config.indexer_cmd);
+        self.check = take(&mut config.indexer_check);
+        self.exts = ext_list(&config.indexer_exts);
+        self.video_exts = ext_list(&config.indexer_video_exts);
+        self.video_frames = config.indexer_video_frames;
     }
 
     fn update_ignore(self: &mut Self, txt: String) {
@@ -175,8 +220,9 @@ impl IdxManager {
         let mut query = con.prepare("select mtime from descriptions where dir = ?1 and fname = ?2").unwrap();
         let ret = match query.query([dir.as_str(), fname.as_ref()]).unwrap().next().unwrap() {
             Some(r) => {
-                let prev_time: f32 = r.get(0).unwrap();
-                match prev_time == mtime {
+                // rows created by the tag editor have no description or mtime yet
+                let prev_time: Option<f32> = r.get(0).unwrap();
+                match prev_time == Some(mtime) {
                     true => Entry::Done,
                     false => Entry::Old,
                 }
@@ -196,6 +242,37 @@ impl IdxManager {
         query.execute((dir, fname, desc, mtime)).unwrap();
     }
 
+    /// videos the indexer command can't read directly are indexed through sampled frames
+    fn sample_frames(&self, path: &Path) -> bool {
+        if self.video_frames == 0 {
+            return false;
+        }
+        match path.extension() {
+            Some(ext) => {
+                let ext = ext.to_ascii_lowercase();
+                let ext = ext.to_string_lossy();
+                self.video_exts.iter().any(|e|e == &ext) && !self.exts.iter().any(|e|e == &ext)
+            },
+            None => false,
+        }
+    }
+
+    /// runs the indexer command on a file and returns its output if it succeeded
+    async fn describe(&self, path: &Path) -> Option<String> {
+        let cmd = format!("{} {}", self.cmd, shquote(path.to_string_lossy().as_ref()));
+        match tokio::process::Command::new("sh").arg("-c").arg(&cmd).output().await {
+            Ok(out) => {
+                if !out.status.success() || out.stdout.is_empty() {
+                    error!("CMD FAILED {}: {}", cmd, unsafe { std::str::from_utf8_unchecked(&out.stderr) });
+                    None
+                } else {
+                    Some(unsafe { std::str::from_utf8_unchecked(&out.stdout) }.to_string())
+                }
+            },
+            Err(e) => {error!("Process error: {}", e); None},
+        }
+    }
+
     /// returns online status if file exists, otherwise true to keep going
     async fn update_file(self: &Self, path: &Path, dir: &String) -> bool {
         let metadata = match path.metadata() {
@@ -211,22 +288,34 @@ impl IdxManager {
         if stat == Entry::Done {
             return true;
         }
-        let cmd = format!("{} {}", self.cmd, shquote(path.to_string_lossy().as_ref()));
-        match tokio::process::Command::new("sh").arg("-c").arg(&cmd).output().await {
-            Ok(out) => {
-                if !out.status.success() || out.stdout.len() == 0 {
-                    error!("CMD FAILED {}: {}", cmd, unsafe { std::str::from_utf8_unchecked(&out.stderr) });
-                    return self.indexer_online().await;
-                } else {
-                    let description = unsafe { std::str::from_utf8_unchecked(&out.stdout) };
-                    trace!("{:?} DESC:{}", path, description.trim());
-                    self.save(dir, &fname, &description, mtime, stat);
-                    return true;
+        let description = if self.sample_frames(path) {
+            let (src, count) = (path.to_path_buf(), self.video_frames);
+            let frames = tokio::task::spawn_blocking(move || extract_frames(&src, count)).await.ok().flatten();
+            // the frames are deleted with their dir when it's dropped
+            let Some((_framedir, frames)) = frames.filter(|(_, frames)| !frames.is_empty()) else {
+                warn!("Could not extract frames from {:?}", path);
+                return true;
+            };
+            let mut descriptions = vec![];
+            for frame in frames.iter() {
+                match self.describe(frame).await {
+                    Some(desc) => descriptions.push(desc.trim().to_string()),
+                    None => break,
                 }
-            },
-            Err(e) => {error!("Process error: {}", e)},
+            }
+            if descriptions.len() < frames.len() {
+                return self.indexer_online().await;
+            }
+            descriptions.into_iter().filter(|d|!d.is_empty()).unique().join("\n")
+        } else {
+            match self.describe(path).await {
+                Some(desc) => desc,
+                None => return self.indexer_online().await,
+            }
         };
-        return self.indexer_online().await;
+        trace!("{:?} DESC:{}", path, description.trim());
+        self.save(dir, &fname, &description, mtime, stat);
+        true
     }
 
     /// returns false if giving up
@@ -238,7 +327,8 @@ impl IdxManager {
                     let path = dir_entry.unwrap().path();
                     match path.extension() {
                         Some(ext) => {
-                            if self.exts.contains(&ext.to_ascii_lowercase().to_string_lossy().as_ref()) {
+                            let ext = ext.to_ascii_lowercase();
+                            if self.exts.iter().any(|e|e.as_str() == ext.to_string_lossy()) || self.sample_frames(&path) {
                                 match self.ignore.matched(&path, path.is_dir()) {
                                     Match::Ignore(_) => continue,
                                     _ => {},
@@ -247,7 +337,8 @@ impl IdxManager {
                                 let mut tries_left = 10;
                                 loop {
                                     if self.shtate.lock().unwrap().paused {
-                                        sleep(Duration::from_secs(60)).await;
+                                        // checked often so indexing picks up right after a resume
+                                        sleep(Duration::from_secs(1)).await;
                                         continue;
                                     }
                                     if online && self.update_file(path.as_path(), dir).await {
@@ -275,6 +366,54 @@ impl IdxManager {
 
 }
 
+/// Saves evenly spaced frames of a video as jpg files that image-only indexers can describe,
+/// in a new private temp dir that's removed when the returned handle is dropped
+fn extract_frames(src: &Path, count: usize) -> Option<(tempfile::TempDir, Vec<PathBuf>)> {
+    let mut decoder = match DecoderBuilder::new(Location::File(src.into()))
+        .with_resize(Resize::Fit(768, 768)).build() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Error opening video {:?}: {}", src, e);
+            return None;
+        },
+    };
+    let (w, h) = decoder.size_out();
+    let duration = decoder.duration().map(|d|d.as_secs()).unwrap_or(0.0);
+    let outdir = match tempfile::Builder::new().prefix("pikeru_frames").tempdir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!("Error creating a dir for video frames: {}", e);
+            return None;
+        },
+    };
+    let mut ret = vec![];
+    for i in 0..count {
+        // sample the middle of each segment to skip black intro and outro frames
+        let ms = (duration * (i as f32 + 0.5) / count as f32 * 1000.0) as i64;
+        if decoder.seek(ms).is_err() {
+            break;
+        }
+        let frame = match decoder.decode() {
+            Ok((_, frame)) => frame,
+            Err(e) => {
+                debug!("Error decoding frame of {:?}: {}", src, e);
+                break;
+            },
+        };
+        let rgb = match frame.as_slice() {
+            Some(s) => s.to_vec(),
+            None => break,
+        };
+        let out = outdir.path().join(format!("frame{}.jpg", i));
+        match img::RgbImage::from_raw(w, h, rgb).map(|im|im.save(&out)) {
+            Some(Ok(_)) => ret.push(out),
+            Some(Err(e)) => error!("Error saving frame {:?}: {}", out, e),
+            None => break,
+        }
+    }
+    Some((outdir, ret))
+}
+
 #[allow(dead_code)]
 struct Indexer {
     tx: USender<Msg>,
@@ -316,21 +455,92 @@ impl Indexer {
 }
 
 struct FilePicker {
-    prev_path: Mutex<String>,
+    prev_path: Mutex<PathBuf>,
+    /// swapped by reload_loop, since zbus holds the interface for as long as a picker is open
+    settings: Arc<RwLock<Arc<PickerSettings>>>,
+    shtate: Arc<Mutex<Shtate>>,
+    db: Arc<Mutex<rusqlite::Connection>>,
+    tx: USender<Msg>,
+}
+/// The file picker settings that can change when the config is reloaded
+struct PickerSettings {
     postproc_dir: String,
     postprocessor: String,
     def_save_dir: String,
     cmd: String,
-    home: String,
-    shtate: Arc<Mutex<Shtate>>,
-    db: Arc<Mutex<rusqlite::Connection>>,
-    tx: USender<Msg>,
+    fallback_cmd: String,
+    timeout: u64,
+    notify: bool,
+    history_days: u32,
+    policies: HashMap<String, AppRules>,
+    postprocess_rules: Vec<postprocess::Rule>,
+    postprocess_keep_hours: u64,
+    strip_metadata: bool,
+    apply_orientation: bool,
 }
+
+impl PickerSettings {
+    fn new(conf: &mut Config) -> Self {
+        Self {
+            postproc_dir: take(&mut conf.postproc_dir),
+            postprocessor: take(&mut conf.postprocessor),
+            def_save_dir: take(&mut conf.def_save_dir),
+            cmd: take(&mut conf.filecmd),
+            fallback_cmd: take(&mut conf.fallback_cmd),
+            timeout: conf.picker_timeout,
+            notify: conf.notify,
+            history_days: conf.history_days,
+            policies: take(&mut conf.policies),
+            postprocess_rules: take(&mut conf.postprocess_rules),
+            postprocess_keep_hours: conf.postprocess_keep_hours,
+            strip_metadata: conf.strip_metadata,
+            apply_orientation: conf.apply_orientation,
+        }
+    }
+}
+
+enum PickerError {
+    TimedOut,
+    /// Exited with an error or a signal, with what it printed to stderr
+    Crashed(String),
+}
+
 enum Section {
     FileChooser,
     Indexer,
+    Policy(String),
+    Postprocess,
     Global,
+    Unknown,
+}
+
+/// Limits for one app from a [policy.APP_ID] section, or every other app from [policy]
+#[derive(Debug, Default)]
+struct AppRules {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    max_size: Option<u64>,
+    postprocessor: Option<String>,
+    strip_metadata: Option<bool>,
+    access: Option<Policy>,
 }
+
+impl AppRules {
+    fn access(&self) -> &Policy {
+        self.access.as_ref().unwrap()
+    }
+
+    /// Options that make the picker hide what the policy rules out, one argument each
+    fn picker_args(&self) -> Vec<String> {
+        let mut args = self.allow.iter().flat_map(|root| ["--allow".to_string(), root.clone()]).collect::<Vec<_>>();
+        args.extend(self.deny.iter().flat_map(|glob| ["--deny".to_string(), glob.clone()]));
+        if let Some(max) = self.max_size {
+            args.extend(["--max-size".to_string(), max.to_string()]);
+        }
+        args
+    }
+}
+
 fn tilda<'a>(home: &String, dir: &'a str) -> Cow<'a,str> {
     if dir.contains('~') {
         let expanded = dir.replace("~", &home);
@@ -341,18 +551,82 @@ fn tilda<'a>(home: &String, dir: &'a str) -> Cow<'a,str> {
 
 #[derive(Debug)]
 struct Config {
+    path: String,
     home: String,
     prev_path: String,
+    log_level: LevelFilter,
     postproc_dir: String,
     postprocessor: String,
     def_save_dir: String,
     filecmd: String,
+    fallback_cmd: String,
+    picker_timeout: u64,
+    notify: bool,
+    history_days: u32,
+    policies: HashMap<String, AppRules>,
+    postprocess_rules: Vec<postprocess::Rule>,
+    postprocess_keep_hours: u64,
+    strip_metadata: bool,
+    apply_orientation: bool,
     indexer_cmd: String,
     indexer_check: String,
     indexer_exts: String,
+    indexer_video_exts: String,
+    indexer_video_frames: usize,
     indexer_enabled: bool,
 }
 
+/// A problem found in the config file. Line 0 means the file as a whole.
+struct Diag {
+    line: usize,
+    error: bool,
+    msg: String,
+}
+
+impl Diag {
+    fn error(line: usize, msg: String) -> Self { Self { line, error: true, msg } }
+    fn warn(line: usize, msg: String) -> Self { Self { line, error: false, msg } }
+}
+
+/// Parses v into dest, or records an error and leaves the default in place
+fn set<T: std::str::FromStr>(dest: &mut T, k: &str, v: &str, line: usize, diags: &mut Vec<Diag>) {
+    match v.parse() {
+        Ok(val) => *dest = val,
+        Err(_) => diags.push(Diag::error(line, format!("invalid value for {}: {}", k, v))),
+    }
+}
+
+/// Filters by log::max_level, which some libraries don't check before logging
+struct ReloadableLogger(env_logger::Logger);
+
+impl log::Log for ReloadableLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+fn parse_level(level: &str) -> Option<LevelFilter> {
+    match level {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
 impl Config {
 
     fn find_config() -> String {
@@ -377,11 +651,13 @@ impl Config {
         String::new()
     }
 
-    fn new() -> Self {
+    /// Returns the config and the log level given on the command line, which overrides the config file
+    fn new() -> (Self, Option<LevelFilter>) {
         let args: Vec<String> = std::env::args().skip(1).collect();
         let mut opts = Options::new();
         opts.optopt("c", "config", "Path to config file", "PATH");
         opts.optopt("l", "log", "Log level", "[off error warn info debug trace]");
+        opts.optflag("", "check-config", "Report errors and warnings in the config file and exit");
         let matches = match opts.parse(args) {
             Ok(m) => m,
             Err(e) => {
@@ -389,8 +665,44 @@ impl Config {
                 std::process::exit(1);
             }
         };
-        let conf_path = matches.opt_str("c").unwrap_or(Config::find_config());
+        let conf_path = matches.opt_str("c").unwrap_or_else(Config::find_config);
         eprintln!("Conf path:{}", conf_path);
+        let (config, diags) = Config::load(&conf_path);
+        Config::report(&conf_path, &diags);
+        if matches.opt_present("check-config") {
+            if diags.is_empty() {
+                println!("{}: OK", conf_path);
+            }
+            std::process::exit(if diags.iter().any(|d|d.error) { 1 } else { 0 });
+        }
+        let log_override = matches.opt_str("l").map(|l| parse_level(&l).unwrap_or_else(|| {
+            eprintln!("Unknown log level:{}. Defaulting to 'info'", l);
+            LevelFilter::Info
+        }));
+        // the level is checked on every record so it can change on reload
+        log::set_boxed_logger(Box::new(ReloadableLogger(Builder::new().filter_level(LevelFilter::Trace).build()))).unwrap();
+        log::set_max_level(log_override.unwrap_or(config.log_level));
+        eprintln!("Log level: {}", log::max_level());
+        if !Path::new(&config.filecmd).is_file() {
+            eprintln!("No filepicker executable found: {}", config.filecmd);
+            std::process::exit(1);
+        }
+        (config, log_override)
+    }
+
+    fn report(conf_path: &str, diags: &[Diag]) {
+        for d in diags {
+            let kind = if d.error { "error" } else { "warning" };
+            match d.line {
+                0 => eprintln!("{}: {}: {}", conf_path, kind, d.msg),
+                n => eprintln!("{}:{}: {}: {}", conf_path, n, kind, d.msg),
+            }
+        }
+    }
+
+    /// Reads the config file, using defaults for anything missing or invalid
+    fn load(conf_path: &str) -> (Self, Vec<Diag>) {
+        let mut diags = vec![];
         let home = std::env::var("HOME").unwrap();
         let mut postproc_dir = "/tmp/pk_postprocess".to_string();
         let mut def_save_dir = Path::new(&home).join("Downloads").to_string_lossy().to_string();
@@ -399,20 +711,52 @@ impl Config {
                     "/opt/pikeru/xdg_portal/contrib/pikeru-wrapper.sh"];
         let mut fp_cmd = fp_cmds.iter().find_map(|c|if Path::new(c).is_file() {Some(*c)} else {None})
             .unwrap_or(fp_cmds[0]).to_string();
+        let mut fp_cmd_line = 0;
+        let mut fallback_cmd = "".to_string();
+        let mut picker_timeout = 0;
+        let mut notify = true;
         let mut postprocessor = "".to_string();
+        let mut history_days = 30;
+        let mut policies = HashMap::<String, AppRules>::new();
+        let mut postprocess_rules = vec![];
+        let mut postprocess_keep_hours = 24;
+        let mut strip_metadata = false;
+        let mut apply_orientation = true;
         let mut indexer_cmd = "".to_string();
         let mut indexer_check = "".to_string();
         let mut indexer_exts = "".to_string();
+        let mut indexer_video_exts = "mp4,mkv,webm,mov,avi,m4v,wmv,flv,mpeg".to_string();
+        let mut indexer_video_frames = 0;
         let mut indexer_enabled = false;
-        let mut log_level = "info".to_string();
-        let txt = std::fs::read_to_string(conf_path).unwrap();
+        let mut log_level = LevelFilter::Info;
+        let txt = match std::fs::read_to_string(conf_path) {
+            Ok(txt) => txt,
+            Err(e) => {
+                diags.push(Diag::error(0, format!("could not read config, using defaults: {}", e)));
+                String::new()
+            },
+        };
         let mut section = Section::Global;
-        for line in txt.lines().map(|s|s.trim()).filter(|s|s.len()>0 && !s.starts_with('#')) {
+        let lines = txt.lines().enumerate().map(|(i,s)|(i+1, s.trim())).filter(|(_,s)|!s.is_empty() && !s.starts_with('#'));
+        for (n, line) in lines {
             match line {
                 "[filepicker]" => section = Section::FileChooser,
                 "[indexer]" => section = Section::Indexer,
+                "[postprocess]" => section = Section::Postprocess,
+                _ if line.starts_with("[policy") && line.ends_with(']') => {
+                    let app_id = line[7..line.len()-1].trim_start_matches('.').to_string();
+                    policies.entry(app_id.clone()).or_default();
+                    section = Section::Policy(app_id);
+                },
+                _ if line.starts_with('[') => {
+                    diags.push(Diag::warn(n, format!("unknown section {}, its values are ignored", line)));
+                    section = Section::Unknown;
+                },
                 _ => {
-                    let (k, v) = str::split_once(line, '=').unwrap();
+                    let Some((k, v)) = str::split_once(line, '=') else {
+                        diags.push(Diag::error(n, format!("expected key = value: {}", line)));
+                        continue;
+                    };
                     let (k, v) = (k.trim(), v.trim());
                     match section {
                         Section::Indexer => {
@@ -420,103 +764,182 @@ impl Config {
                                 "cmd" => indexer_cmd = v.to_string(),
                                 "check" => indexer_check = v.to_string(),
                                 "extensions" => indexer_exts = v.to_string(),
-                                "enable" => indexer_enabled = v.parse().unwrap(),
-                                _ => eprintln!("Unknown indexer config value:{}", line),
+                                "video_extensions" => indexer_video_exts = v.to_string(),
+                                "video_frames" => set(&mut indexer_video_frames, k, v, n, &mut diags),
+                                "enable" => set(&mut indexer_enabled, k, v, n, &mut diags),
+                                _ => diags.push(Diag::warn(n, format!("unknown indexer option {}", k))),
                             }
                         },
                         Section::FileChooser => {
                             match k {
-                                "cmd" => fp_cmd = v.to_string(),
+                                "cmd" => { fp_cmd = v.to_string(); fp_cmd_line = n; },
                                 "default_save_dir" => def_save_dir = v.to_string(),
                                 "postprocess_dir" => postproc_dir = v.to_string(),
                                 "postprocessor" => postprocessor = v.to_string(),
-                                _ => eprintln!("Unknown filechooser config value:{}", line),
+                                "fallback_cmd" => {
+                                    fallback_cmd = tilda(&home, v).to_string();
+                                    if !fallback_cmd.is_empty() && !Path::new(&fallback_cmd).is_file() {
+                                        diags.push(Diag::warn(n, format!("fallback_cmd {} not found", fallback_cmd)));
+                                    }
+                                },
+                                "timeout" => set(&mut picker_timeout, k, v, n, &mut diags),
+                                "notify" => set(&mut notify, k, v, n, &mut diags),
+                                "history_days" => set(&mut history_days, k, v, n, &mut diags),
+                                "strip_metadata" => set(&mut strip_metadata, k, v, n, &mut diags),
+                                "apply_orientation" => set(&mut apply_orientation, k, v, n, &mut diags),
+                                _ => diags.push(Diag::warn(n, format!("unknown filepicker option {}", k))),
+                            }
+                        },
+                        Section::Policy(ref app_id) => {
+                            let rules = policies.get_mut(app_id).unwrap();
+                            let list = || v.split(',').map(|s|tilda(&home, s.trim()).to_string()).filter(|s|!s.is_empty());
+                            match k {
+                                "allow" => rules.allow.extend(list()),
+                                "deny" => rules.deny.extend(list()),
+                                "max_size" => match parse_size(v) {
+                                    Some(size) => rules.max_size = Some(size),
+                                    None => diags.push(Diag::error(n, format!("invalid max_size: {}", v))),
+                                },
+                                "postprocessor" => {
+                                    let pp = tilda(&home, v).to_string();
+                                    if !Path::new(&pp).is_file() {
+                                        diags.push(Diag::warn(n, format!("postprocessor {} not found, requests from {} will be refused",
+                                                                         pp, if app_id.is_empty() { "any app" } else { app_id })));
+                                    }
+                                    rules.postprocessor = Some(pp);
+                                },
+                                "strip_metadata" => {
+                                    let mut strip = false;
+                                    set(&mut strip, k, v, n, &mut diags);
+                                    rules.strip_metadata = Some(strip);
+                                },
+                                _ => diags.push(Diag::warn(n, format!("unknown policy option {}", k))),
+                            }
+                        },
+                        Section::Postprocess => {
+                            match k {
+                                "keep_hours" => set(&mut postprocess_keep_hours, k, v, n, &mut diags),
+                                _ => match postprocess::Rule::parse(k, v) {
+                                    Ok(rule) => postprocess_rules.push(rule),
+                                    Err(e) => diags.push(Diag::error(n, format!("bad postprocess rule, {}", e))),
+                                },
                             }
                         },
                         Section::Global => {
                             match k {
-                                "log_level" => log_level = v.to_string(),
-                                _ => {},
+                                "log_level" => match parse_level(v) {
+                                    Some(level) => log_level = level,
+                                    None => diags.push(Diag::warn(n, format!("unknown log level {}, using info", v))),
+                                },
+                                _ => diags.push(Diag::warn(n, format!("unknown option {}", k))),
                             }
                         },
+                        Section::Unknown => {},
                     }
                 }
             }
         }
-        matches.opt_str("l").map(|l|log_level = l);
-        let ll = match log_level.as_str() {
-            "off" => LevelFilter::Off,
-            "error" => LevelFilter::Error,
-            "warn" => LevelFilter::Warn,
-            "info" => LevelFilter::Info,
-            "debug" => LevelFilter::Debug,
-            "trace" => LevelFilter::Trace,
-            _ => { eprintln!("Unknown log level:{}. Defaulting to 'info'", log_level); LevelFilter::Info },
-        };
-        Builder::new().filter_level(ll).init();
-        eprintln!("Log level: {}", ll);
+        let fp_cmd = tilda(&home, &fp_cmd).to_string();
         if !Path::new(&fp_cmd).is_file() {
-            eprintln!("No filepicker executable found: {}", fp_cmd);
-            std::process::exit(1);
+            diags.push(Diag::error(fp_cmd_line, format!("no filepicker executable found: {}", fp_cmd)));
         }
-        Self {
+        if indexer_enabled && indexer_cmd.is_empty() {
+            diags.push(Diag::warn(0, "indexer is enabled but has no cmd".to_string()));
+        }
+        policies.values_mut().for_each(|rules| {
+            rules.access = Some(Policy::new(&rules.allow, &rules.deny, rules.max_size));
+        });
+        (Self {
+            path: conf_path.to_string(),
             prev_path: home.clone(),
+            log_level,
             postproc_dir: tilda(&home, &postproc_dir).to_string(),
             postprocessor: tilda(&home, &postprocessor).to_string(),
             def_save_dir: tilda(&home, &def_save_dir).to_string(),
-            filecmd: tilda(&home, &fp_cmd).to_string(),
+            filecmd: fp_cmd,
+            fallback_cmd,
+            picker_timeout,
+            notify,
+            history_days,
+            policies,
+            postprocess_rules,
+            postprocess_keep_hours,
+            strip_metadata,
+            apply_orientation,
             indexer_cmd: tilda(&home, &indexer_cmd).to_string(),
             indexer_check: tilda(&home, &indexer_check).to_string(),
             indexer_exts,
+            indexer_video_exts,
+            indexer_video_frames,
             indexer_enabled,
             home,
-        }
+        }, diags)
     }
 }
 
 impl FilePicker {
 
-    fn new(conf: &mut Config, shtate: Arc<Mutex<Shtate>>, tx: USender<Msg>, db: Arc<Mutex<rusqlite::Connection>>) -> Self {
+    fn new(conf: &mut Config, settings: Arc<RwLock<Arc<PickerSettings>>>, shtate: Arc<Mutex<Shtate>>,
+           tx: USender<Msg>, db: Arc<Mutex<rusqlite::Connection>>) -> Self {
+        db.lock().unwrap().execute(
+            "create table if not exists history (time integer, app_id text, mode text, paths text, postprocessed integer)",
+            ()).unwrap();
         Self {
-            prev_path: Mutex::new(take(&mut conf.prev_path)),
-            postproc_dir: take(&mut conf.postproc_dir),
-            postprocessor: take(&mut conf.postprocessor),
-            def_save_dir: take(&mut conf.def_save_dir),
-            cmd: take(&mut conf.filecmd),
-            home: take(&mut conf.home),
+            prev_path: Mutex::new(PathBuf::from(take(&mut conf.prev_path))),
+            settings,
             shtate,
             db,
             tx,
         }
     }
 
-    async fn select_files(self: &Self, multi: bool, dir: bool, save: bool, path: &str) -> (u32, HashMap<String, OwnedValue>) {
-        let dir = if dir   { 1 } else { 0 };
-        let multi = if multi { 1 } else { 0 };
-        let savenum = if save  { 1 } else { 0 };
-        let cmd = if save {
-            format!("{} {} {} {} \"{}\"", self.cmd, multi, dir, savenum, tilda(&self.home,path))
-        } else {
-            format!("POSTPROCESS_DIR=\"{}\" POSTPROCESSOR=\"{}\" {} {} {} {} {}",
-                    self.postproc_dir, self.postprocessor, self.cmd, multi, dir, savenum,
-                    shquote(tilda(&self.home,&self.prev_path.lock().unwrap()).as_ref()))
+    /// path is where the picker starts, or for saving the suggested file. Opening without one starts where the last request ended.
+    async fn select_files(self: &Self, app_id: &str, multi: bool, dir: bool, save: bool, path: Option<PathBuf>) -> (u32, HashMap<String, OwnedValue>) {
+        // a snapshot, so a reload while the picker is open applies from the next request
+        let conf = self.settings.read().unwrap().clone();
+        let mode = if save { "save" } else if dir && multi { "directories" } else if dir { "directory" } else if multi { "open_multiple" } else { "open" };
+        let policy = conf.policies.get(app_id).or(conf.policies.get(""));
+        let postprocessor = match policy.and_then(|p|p.postprocessor.as_ref()) {
+            Some(pp) if !Path::new(pp).is_file() => {
+                error!("Refusing request from {}, required postprocessor {} not found", app_id, pp);
+                return (1, HashMap::new());
+            },
+            Some(pp) => pp,
+            None => &conf.postprocessor,
+        };
+        let start = path.unwrap_or_else(|| self.prev_path.lock().unwrap().clone());
+        let start = match policy.map(|p|(p.access(), p.access().start_dir())) {
+            Some((access, Some(root))) if !access.allows(&start, true) => root.to_path_buf(),
+            _ => start,
+        };
+        let flag = |b: bool| if b { "1" } else { "0" };
+        // arguments are passed as is so paths that aren't valid UTF-8 or need quoting reach the picker intact
+        let command = |picker: &str| {
+            let mut cmd = tokio::process::Command::new(picker);
+            cmd.args([flag(multi), flag(dir), flag(save)]).arg(&start)
+                .args(policy.map(|p|p.picker_args()).unwrap_or_default());
+            cmd
         };
         self.db.lock().unwrap().cache_flush().unwrap();
-        debug!("CMD:{}", cmd);
         self.shtate.lock().unwrap().picker_open = true;
-        let output = match tokio::process::Command::new("sh").arg("-c").arg(cmd).output().await {
-            Ok(out) => {
-                if out.stderr.len() > 0 {
-                    let txt = unsafe { std::str::from_utf8_unchecked(&out.stderr) };
-                    if out.status.success() {
-                        info!("From filepicker:{}", txt);
-                    } else {
-                        error!("From filepicker:{}", txt);
-                    }
+        let output = match self.run_picker(&conf, command(&conf.cmd)).await {
+            Ok(out) => out,
+            Err(PickerError::TimedOut) => {
+                self.report_failure(&conf, &format!("The file picker was closed after {} seconds", conf.timeout)).await;
+                vec![]
+            },
+            Err(PickerError::Crashed(e)) if !conf.fallback_cmd.is_empty() => {
+                self.report_failure(&conf, &format!("{}\nUsing {} instead", e, conf.fallback_cmd)).await;
+                match self.run_picker(&conf, command(&conf.fallback_cmd)).await {
+                    Ok(out) => out,
+                    Err(PickerError::TimedOut) => vec![],
+                    Err(PickerError::Crashed(e)) => { self.report_failure(&conf, &e).await; vec![] },
                 }
-                unsafe { std::str::from_utf8_unchecked(&out.stdout).to_owned() }
             },
-            Err(e) => {eprintln!("Process error: {}", e); "".to_owned()},
+            Err(PickerError::Crashed(e)) => {
+                self.report_failure(&conf, &e).await;
+                vec![]
+            },
         };
         match self.shtate.lock() {
             Ok(mut mtx) => {
@@ -527,16 +950,60 @@ impl FilePicker {
             },
             Err(e) => eprintln!("MTX error: {}", e),
         }
-        let mut gotfirst = false;
-        let arr = output.lines().map(|line| {
-            if !gotfirst {
-                gotfirst = true;
-                if let Some(par_dir) = self.get_dir(line) {
-                   *self.prev_path.lock().unwrap() = par_dir;
-                }
+        // the picker hides what's off limits, but a typed path could still get through.
+        // Checked before postprocessing so only what the picker returned is judged.
+        let lines = output.split(|b|*b == b'\n').filter(|line|!line.is_empty())
+            .map(|line| PathBuf::from(OsStr::from_bytes(line))).filter(|line| {
+            let allowed = match policy {
+                Some(p) => p.access().allows(line, false),
+                None => true,
+            };
+            if !allowed {
+                warn!("Policy for {} blocked {:?}", app_id, line);
             }
-            format!("file://{}",line)
+            allowed
         }).collect::<Vec<_>>();
+        if let Some(par_dir) = lines.first().and_then(|line|get_dir(&conf, line)) {
+            *self.prev_path.lock().unwrap() = par_dir;
+        }
+        let strip = policy.and_then(|p|p.strip_metadata).unwrap_or(conf.strip_metadata);
+        let postprocessor = Some(Path::new(postprocessor)).filter(|pp| pp.is_file());
+        let outdir = if !save && !lines.is_empty() && (strip || postprocessor.is_some() || !conf.postprocess_rules.is_empty()) {
+            postprocess::clean(Path::new(&conf.postproc_dir), conf.postprocess_keep_hours);
+            match postprocess::request_dir(Path::new(&conf.postproc_dir)) {
+                Some(outdir) => Some(outdir),
+                // without somewhere to write clean copies the originals would go out as is
+                None if strip || postprocessor.is_some() => return (1, HashMap::new()),
+                None => None,
+            }
+        } else { None };
+        let lines = match (&outdir, postprocessor) {
+            (Some(outdir), Some(pp)) => match tokio::task::block_in_place(|| postprocess::script(pp, outdir, lines)) {
+                Some(lines) => lines,
+                None => {
+                    let _ = std::fs::remove_dir_all(outdir);
+                    return (1, HashMap::new());
+                },
+            },
+            _ => lines,
+        };
+        let lines = match &outdir {
+            Some(outdir) if !conf.postprocess_rules.is_empty() =>
+                tokio::task::block_in_place(|| postprocess::run(&conf.postprocess_rules, outdir, app_id, lines)),
+            _ => lines,
+        };
+        let lines = match &outdir {
+            Some(outdir) if strip => tokio::task::block_in_place(|| postprocess::strip_metadata(outdir, conf.apply_orientation, lines)),
+            _ => lines,
+        };
+        // only removed when nothing needed converting
+        if let Some(outdir) = outdir {
+            let _ = std::fs::remove_dir(outdir);
+        }
+        let arr = lines.iter().map(|line| file_uri(line)).collect::<Vec<_>>();
+        if !arr.is_empty() {
+            self.record(&conf, app_id, mode, &lines);
+        }
         let mut ret = HashMap::new();
         let status = if arr.is_empty() { 1 } else {
             ret.insert("uris".to_string(), Value::from(arr).try_to_owned().unwrap());
@@ -545,21 +1012,133 @@ impl FilePicker {
         (status, ret)
     }
 
-    fn get_dir(self: &Self, path: &str) -> Option<String> {
-        let p = Path::new(path);
-        let parent = p.parent()?;
-        let ps =  parent.to_string_lossy();
-        if !parent.is_dir() || ps == self.postproc_dir {
-            return None;
+    /// Runs a picker command and returns what it printed, killing it if it's open longer than the timeout
+    async fn run_picker(&self, conf: &PickerSettings, mut cmd: tokio::process::Command) -> Result<Vec<u8>, PickerError> {
+        debug!("CMD:{:?}", cmd.as_std());
+        // its own process group so a timeout closes the picker and not just the wrapper script running it
+        let mut child = match cmd.process_group(0).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(e) => return Err(PickerError::Crashed(format!("Could not start the file picker: {}", e))),
+        };
+        let stdout = tokio::spawn(read_pipe(child.stdout.take()));
+        let stderr = tokio::spawn(read_pipe(child.stderr.take()));
+        let limit = async {
+            match conf.timeout {
+                0 => pending::<()>().await,
+                secs => sleep(Duration::from_secs(secs)).await,
+            }
+        };
+        let status = tokio::select! {
+            status = child.wait() => status,
+            _ = limit => {
+                warn!("File picker open longer than {} seconds, closing it", conf.timeout);
+                if let Some(pid) = child.id() {
+                    let _ = tokio::process::Command::new("kill").arg("--").arg(format!("-{}", pid)).status().await;
+                }
+                let _ = child.kill().await;
+                return Err(PickerError::TimedOut);
+            },
+        };
+        let (out, err) = (stdout.await.unwrap_or_default(), stderr.await.unwrap_or_default());
+        let err = String::from_utf8_lossy(&err);
+        let failure = match status {
+            Ok(status) if status.success() => None,
+            // the shell reports a picker killed by a signal as 128 + the signal
+            Ok(status) => match status.signal().or(status.code().filter(|c|*c > 128).map(|c|c - 128)) {
+                Some(sig) => Some(format!("The file picker was killed by signal {}", sig)),
+                None => Some(format!("The file picker exited with code {}", status.code().unwrap_or_default())),
+            },
+            Err(e) => Some(format!("Error waiting for the file picker: {}", e)),
+        };
+        match failure {
+            None => {
+                if !err.is_empty() {
+                    info!("From filepicker:{}", err);
+                }
+                Ok(out)
+            },
+            Some(msg) => Err(PickerError::Crashed(match err.trim() {
+                "" => msg,
+                err => format!("{}:\n{}", msg, err),
+            })),
         }
-        Some(ps.to_string()) 
     }
 
+    /// Logs a picker failure and shows it as a desktop notification so it doesn't go unnoticed
+    async fn report_failure(&self, conf: &PickerSettings, msg: &str) {
+        error!("{}", msg);
+        if !conf.notify {
+            return;
+        }
+        // the end of stderr is usually what explains the failure
+        let body = msg.lines().rev().take(10).collect::<Vec<_>>().into_iter().rev().join("\n");
+        let sent = match zbus::Connection::session().await {
+            Ok(conn) => conn.call_method(Some("org.freedesktop.Notifications"), "/org/freedesktop/Notifications",
+                Some("org.freedesktop.Notifications"), "Notify",
+                &("Pikeru", 0u32, "dialog-error", "File picker failed", body, Vec::<&str>::new(),
+                  HashMap::<&str, Value>::new(), -1i32)).await.map(|_|()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            warn!("Could not send notification: {}", e);
+        }
+    }
+
+    /// Logs which files were handed to which app and drops entries older than history_days
+    fn record(&self, conf: &PickerSettings, app_id: &str, mode: &str, paths: &[PathBuf]) {
+        if conf.history_days == 0 {
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let postproc_dir = Path::new(&conf.postproc_dir);
+        let postprocessed = paths.iter().any(|p| p.starts_with(postproc_dir));
+        // in the picker's form, so names that aren't UTF-8 come back byte for byte
+        let paths = paths.iter().map(rawpath::to_str).collect::<Vec<_>>();
+        let con = self.db.lock().unwrap();
+        if let Err(e) = con.execute("insert into history (time, app_id, mode, paths, postprocessed) values (?1, ?2, ?3, ?4, ?5)",
+                                    (now, app_id, mode, paths.join("\n"), postprocessed)) {
+            error!("Error saving history: {}", e);
+        }
+        if let Err(e) = con.execute("delete from history where time < ?1", (now - conf.history_days as i64 * 86400,)) {
+            error!("Error pruning history: {}", e);
+        }
+    }
+
+}
+
+fn get_dir(conf: &PickerSettings, path: &Path) -> Option<PathBuf> {
+    let parent = path.parent()?;
+    if !parent.is_dir() || parent.starts_with(&conf.postproc_dir) {
+        return None;
+    }
+    Some(parent.to_path_buf())
+}
+
+/// Reads a path option, which apps send as a nul terminated byte array since paths don't have to be UTF-8
+fn byte_path(options: &HashMap<&str, Value<'_>>, key: &str) -> Option<PathBuf> {
+    let bytes = match options.get(key)? {
+        Value::Array(a) => a.iter().map(|v| match v {
+            Value::U8(b) => Some(*b),
+            _ => None,
+        }).collect::<Option<Vec<u8>>>(),
+        _ => None,
+    };
+    let Some(mut bytes) = bytes else {
+        error!("{} is not a byte array", key);
+        return None;
+    };
+    if let Some(nul) = bytes.iter().position(|b|*b == 0) {
+        bytes.truncate(nul);
+    }
+    match bytes.is_empty() {
+        true => None,
+        false => Some(PathBuf::from(OsString::from_vec(bytes))),
+    }
 }
 
 #[interface(name = "org.freedesktop.impl.portal.FileChooser")]
 impl FilePicker {
-    async fn open_file(&self, _ob: ObjectPath<'_>, _caller: &str, _parent: &str,
+    async fn open_file(&self, _ob: ObjectPath<'_>, app_id: &str, _parent: &str,
                  _title: &str, options: HashMap<&str, Value<'_>>) -> (u32, HashMap<String, OwnedValue>) {
         let dir = match options.get("directory").unwrap_or(&Value::Bool(false)) {
             &Value::Bool(b) => b,
@@ -569,44 +1148,118 @@ impl FilePicker {
             &Value::Bool(b) => b,
             _ => { error!("MULTI type error"); false},
         };
-        self.select_files(multi, dir, false, "/").await
+        self.select_files(app_id, multi, dir, false, byte_path(&options, "current_folder")).await
     }
 
-    async fn save_file(&self, _ob: ObjectPath<'_>, _caller: &str, _parent: &str,
+    async fn save_file(&self, _ob: ObjectPath<'_>, app_id: &str, _parent: &str,
                  _title: &str, options: HashMap<&str, Value<'_>>) -> (u32, HashMap<String, OwnedValue>) {
-        let dir = match options.get("current_folder").unwrap_or(&Value::from(&self.def_save_dir)) {
-            Value::Array(s) => {
-                let b = to_bytes(Context::new_dbus(LE, 0), s).unwrap();
-                match std::str::from_utf8(&b[4..b.len()-1]) {
-                    Ok(s) => s.to_string(),
-                    Err(e) => {
-                        error!("Error reading dir:{}", e);
-                        self.def_save_dir.clone()
-                    },
-                }
-            },
-            _ => self.def_save_dir.clone(),
-        };
-        let fname = match options.get("current_name").unwrap_or(&Value::from("download")) {
-            Value::Str(s) => s.to_string(),
-            _ => "download".to_string(),
+        // current_file is an existing file to overwrite, otherwise suggest current_name in current_folder
+        let path = byte_path(&options, "current_file").unwrap_or_else(|| {
+            let dir = byte_path(&options, "current_folder")
+                .unwrap_or_else(|| PathBuf::from(&self.settings.read().unwrap().def_save_dir));
+            let fname = match options.get("current_name") {
+                Some(Value::Str(s)) if !s.is_empty() => s.to_string(),
+                _ => "download".to_string(),
+            };
+            dir.join(fname)
+        });
+        self.select_files(app_id, false, false, true, Some(path)).await
+    }
+}
+
+/// Sends a message whenever the config file is written or replaced
+fn watch_config(path: &str, tx: USender<()>) {
+    let path = PathBuf::from(path);
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir.to_path_buf(), name.to_os_string()),
+        _ => return,
+    };
+    std::thread::spawn(move || {
+        let mut ino = match Inotify::init() {
+            Ok(ino) => ino,
+            Err(e) => { error!("Can't watch config for changes: {}", e); return },
         };
-        let path = Path::new(&dir).join(fname);
-        self.select_files(false, false, true, &path.to_string_lossy()).await
+        // editors often save by replacing the file, so watch the directory
+        if let Err(e) = ino.watches().add(&dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE) {
+            error!("Can't watch {:?} for config changes: {}", dir, e);
+            return;
+        }
+        let mut buf = [0; 4096];
+        loop {
+            match ino.read_events_blocking(&mut buf) {
+                Ok(mut events) => if events.any(|e| e.name == Some(name.as_os_str())) && tx.send(()).is_err() {
+                    return;
+                },
+                Err(e) => { error!("Error watching config: {}", e); return },
+            }
+        }
+    });
+}
+
+/// Reloads the config on SIGHUP or when the file changes, applying it without
+/// dropping the D-Bus name. A config with errors is reported and not applied.
+async fn reload_loop(settings: Arc<RwLock<Arc<PickerSettings>>>, conf_path: String, log_override: Option<LevelFilter>, tx: USender<Msg>) {
+    let (rtx, mut rrx) = unbounded_channel::<()>();
+    watch_config(&conf_path, rtx.clone());
+    match signal(SignalKind::hangup()) {
+        Ok(mut hup) => { tokio::spawn(async move {
+            while hup.recv().await.is_some() {
+                if rtx.send(()).is_err() { break; }
+            }
+        }); },
+        Err(e) => error!("Can't listen for SIGHUP: {}", e),
+    }
+    while rrx.recv().await.is_some() {
+        // let the editor finish writing before reading
+        sleep(Duration::from_millis(200)).await;
+        while rrx.try_recv().is_ok() {}
+        let (mut config, diags) = Config::load(&conf_path);
+        Config::report(&conf_path, &diags);
+        if diags.iter().any(|d|d.error) {
+            error!("Config has errors, keeping the running config");
+            continue;
+        }
+        log::set_max_level(log_override.unwrap_or(config.log_level));
+        *settings.write().unwrap() = Arc::new(PickerSettings::new(&mut config));
+        if tx.send(Msg::Reload(Box::new(config))).is_err() {
+            error!("Indexer stopped, can't reload its config");
+        }
+        info!("Reloaded config");
     }
 }
 
+/// Flushes the index before exiting on SIGINT or SIGTERM
+async fn exit_on_signal(db: Arc<Mutex<rusqlite::Connection>>) {
+    let mut term = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = term.recv() => {},
+    }
+    if let Err(e) = db.lock().unwrap().cache_flush() {
+        eprintln!("failed to flush index db:{}", e);
+    }
+    eprintln!("Portal closing");
+    std::process::exit(0);
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let mut config = Config::new();
+    let (mut config, log_override) = Config::new();
     eprintln!("Running {:#?}", config);
+    // once for the whole process, since the indexer reads video frames whenever a reload turns it on
+    if let Err(e) = video_rs::init() {
+        error!("Can't initialize video decoding: {}", e);
+    }
+    let conf_path = take(&mut config.path);
     let idxfile = Path::new(&config.home).join(".cache").join("pikeru").join("index.db");
     let sht = Arc::new(Mutex::new(Shtate::default()));
     let (tx, rx) = unbounded_channel::<Msg>();
     std::fs::create_dir_all(idxfile.parent().unwrap()).unwrap();
     let db = Arc::new(Mutex::new(rusqlite::Connection::open(idxfile).unwrap()));
-    let picker = FilePicker::new(&mut config, sht.clone(), tx.clone(), db.clone());
-    let indexer = Indexer::new(tx, sht.clone(), db.clone());
+    tokio::spawn(exit_on_signal(db.clone()));
+    let settings = Arc::new(RwLock::new(Arc::new(PickerSettings::new(&mut config))));
+    let picker = FilePicker::new(&mut config, settings.clone(), sht.clone(), tx.clone(), db.clone());
+    let indexer = Indexer::new(tx.clone(), sht.clone(), db.clone());
     let manager = IdxManager::new(sht.clone(), &mut config, db);
     tokio::spawn(index_loop(manager, rx, config.indexer_enabled));
     let _conn = connection::Builder::session()?
@@ -615,6 +1268,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .serve_at("/org/freedesktop/portal/desktop", indexer)?
         .build()
         .await?;
+    if !conf_path.is_empty() {
+        tokio::spawn(reload_loop(settings, conf_path, log_override, tx));
+    }
     pending::<()>().await;
     Ok(())
 }
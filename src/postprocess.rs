@@ -0,0 +1,265 @@
+// Built-in postprocessing rules from the [postprocess] section of the portal config.
+// Each rule line is `conditions = actions`, for example
+// `ext:webp mime:image/* app:org.mozilla.firefox = format:jpg max_dim:2048 quality:85 max_size:2M`
+// Every request writes to its own directory in postproc_dir, so outputs an app may
+// still be reading are never overwritten by a later request.
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    io::{Cursor, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
+use img::{DynamicImage, ImageFormat, imageops::FilterType, codecs::jpeg::JpegEncoder};
+use log::{debug, error, warn};
+use super::parse_size;
+use super::exif;
+
+/// Start of the names of per-request output dirs, the only things clean deletes
+const REQUEST_PREFIX: &str = "request-";
+
+#[derive(Debug, Default)]
+pub struct Rule {
+    exts: Vec<String>,
+    mimes: Vec<String>,
+    apps: Vec<String>,
+    format: Option<ImageFormat>,
+    max_dim: Option<u32>,
+    quality: Option<u8>,
+    max_size: Option<u64>,
+}
+
+impl Rule {
+    pub fn parse(conditions: &str, actions: &str) -> Result<Self, String> {
+        let mut rule = Rule::default();
+        for cond in conditions.split_whitespace() {
+            match cond.split_once(':') {
+                Some(("ext", v)) => rule.exts.extend(v.split(',').map(|e|e.trim_start_matches('.').to_ascii_lowercase())),
+                Some(("mime", v)) => rule.mimes.extend(v.split(',').map(str::to_string)),
+                Some(("app", v)) => rule.apps.extend(v.split(',').map(str::to_string)),
+                _ => return Err(format!("unknown condition {}", cond)),
+            }
+        }
+        for action in actions.split_whitespace() {
+            match action.split_once(':') {
+                Some(("format", v)) => rule.format = Some(ImageFormat::from_extension(v)
+                    .filter(|f|f.can_write()).ok_or(format!("can't write format {}", v))?),
+                Some(("max_dim", v)) => rule.max_dim = Some(v.parse().map_err(|_|format!("bad max_dim {}", v))?),
+                Some(("quality", v)) => rule.quality = Some(v.parse::<u8>().ok().filter(|q|(1..=100).contains(q))
+                    .ok_or(format!("quality must be 1-100, got {}", v))?),
+                Some(("max_size", v)) => rule.max_size = Some(parse_size(v).ok_or(format!("bad max_size {}", v))?),
+                _ => return Err(format!("unknown action {}", action)),
+            }
+        }
+        Ok(rule)
+    }
+
+    fn matches(&self, path: &Path, format: ImageFormat, app_id: &str) -> bool {
+        let ext = path.extension().unwrap_or_default().to_ascii_lowercase();
+        let mime = format.to_mime_type();
+        (self.exts.is_empty() || self.exts.iter().any(|e| ext.to_string_lossy() == e.as_str()))
+            && (self.mimes.is_empty() || self.mimes.iter().any(|m| match m.strip_suffix('*') {
+                Some(prefix) => mime.starts_with(prefix),
+                None => mime == m,
+            }))
+            && (self.apps.is_empty() || self.apps.iter().any(|a| a == app_id))
+    }
+
+    /// Encodes the image, lowering jpg quality and then dimensions until it fits in max_size
+    fn encode(&self, mut image: DynamicImage, format: ImageFormat) -> img::ImageResult<Vec<u8>> {
+        if let Some(max) = self.max_dim {
+            if image.width() > max || image.height() > max {
+                image = image.resize(max, max, FilterType::Lanczos3);
+            }
+        }
+        let mut quality = self.quality.unwrap_or(90);
+        loop {
+            let data = encode(&image, format, quality)?;
+            match self.max_size {
+                Some(max) if data.len() as u64 > max && image.width() > 64 && image.height() > 64 => {
+                    if format == ImageFormat::Jpeg && quality > 50 {
+                        quality -= 10;
+                    } else {
+                        image = image.resize(image.width() * 3 / 4, image.height() * 3 / 4, FilterType::Lanczos3);
+                    }
+                },
+                _ => return Ok(data),
+            }
+        }
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat, quality: u8) -> img::ImageResult<Vec<u8>> {
+    let mut buf = Cursor::new(vec![]);
+    match format {
+        ImageFormat::Jpeg => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut buf, quality))?,
+        _ => image.write_to(&mut buf, format)?,
+    }
+    Ok(buf.into_inner())
+}
+
+/// Pipes the paths through a postprocessor script with POSTPROCESS_DIR set to outdir.
+/// Only the paths it was given and files it wrote in outdir are accepted back, so the
+/// script can't hand over anything the policy didn't already allow.
+/// None when the script fails, so the request can be refused instead of passing on originals.
+pub fn script(script: &Path, outdir: &Path, paths: Vec<PathBuf>) -> Option<Vec<PathBuf>> {
+    let mut child = match Command::new("bash").arg(script).env("POSTPROCESS_DIR", outdir)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => { error!("Could not run postprocessor {:?}: {}", script, e); return None },
+    };
+    let input = paths.iter().flat_map(|p| [p.as_os_str().as_bytes(), b"\n"].concat()).collect::<Vec<u8>>();
+    let mut stdin = child.stdin.take()?;
+    // written from another thread so a script printing before it reads everything can't deadlock
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output();
+    let _ = writer.join();
+    let output = match output {
+        Ok(out) if out.status.success() => out.stdout,
+        Ok(out) => { error!("Postprocessor {:?} failed with {}", script, out.status); return None },
+        Err(e) => { error!("Error waiting for postprocessor {:?}: {}", script, e); return None },
+    };
+    Some(output.split(|b|*b == b'\n').filter(|line|!line.is_empty()).map(|line| PathBuf::from(OsStr::from_bytes(line)))
+        .filter(|out| {
+            let produced = out.parent() == Some(outdir)
+                && std::fs::symlink_metadata(out).is_ok_and(|m| m.is_file());
+            if !produced && !paths.contains(out) {
+                warn!("Postprocessor returned {:?}, which wasn't selected or written to {:?}", out, outdir);
+                return false;
+            }
+            true
+        }).collect())
+}
+
+/// Rewrites each file with the first rule that matches it into outdir and returns
+/// the paths to hand to the app. Files no rule applies to are returned unchanged.
+pub fn run(rules: &[Rule], outdir: &Path, app_id: &str, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    if let Err(e) = std::fs::create_dir_all(outdir) {
+        error!("Error creating {:?}: {}", outdir, e);
+        return paths;
+    }
+    let mut used = HashSet::new();
+    paths.into_iter().map(|path| {
+        let src = path.as_path();
+        let Ok(format) = ImageFormat::from_path(src) else { return path };
+        let Some(rule) = rules.iter().find(|r| r.matches(src, format, app_id)) else { return path };
+        let out_format = rule.format.unwrap_or(format);
+        if !out_format.can_write() {
+            warn!("Can't write {:?} files, leaving {:?} as is", out_format, path);
+            return path;
+        }
+        let out = out_path(outdir, src, out_format, &mut used);
+        let data = img::open(src).and_then(|image| rule.encode(image, out_format));
+        match data.map(|data| std::fs::write(&out, data)) {
+            Ok(Ok(_)) => {
+                debug!("Postprocessed {:?} to {:?}", path, out);
+                out
+            },
+            Ok(Err(e)) => { error!("Error writing {:?}: {}", out, e); path },
+            Err(e) => { error!("Error postprocessing {:?}: {}", path, e); path },
+        }
+    }).collect()
+}
+
+/// Copies images with metadata into outdir without it and returns the paths to hand to the app.
+/// Dropping EXIF also drops the orientation, so with apply_orientation rotated photos are
+/// re-encoded upright instead. Files the metadata can't be parsed out of are re-encoded from
+/// their pixels, and files that can't be cleaned either way are left out rather than passed on.
+pub fn strip_metadata(outdir: &Path, apply_orientation: bool, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut used: HashSet<PathBuf> = paths.iter().filter(|p| p.parent() == Some(outdir)).cloned().collect();
+    paths.into_iter().filter_map(|path| {
+        let src = path.as_path();
+        let format = match ImageFormat::from_path(src) {
+            Ok(f @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => f,
+            _ => return Some(path),
+        };
+        let data = match std::fs::read(src) {
+            Ok(data) => data,
+            Err(e) => { error!("Error reading {:?}, leaving it out: {}", path, e); return None },
+        };
+        let orientation = exif::orientation(&data);
+        let reencode = |why: &str| img::load_from_memory(&data).and_then(|image| encode(&upright(image, orientation), format, 95))
+            .map_err(|e| error!("Error {} {:?}, leaving it out: {}", why, path, e)).ok();
+        let stripped = if apply_orientation && orientation > 1 {
+            reencode("rotating")?
+        } else {
+            match exif::strip(&data) {
+                exif::Stripped::Clean => return Some(path),
+                exif::Stripped::Removed(data) => data,
+                exif::Stripped::Unreadable => {
+                    warn!("Couldn't parse the metadata in {:?}, re-encoding it", path);
+                    reencode("re-encoding")?
+                },
+            }
+        };
+        let out = if src.parent() == Some(outdir) { src.to_path_buf() } else { out_path(outdir, src, format, &mut used) };
+        match std::fs::write(&out, stripped) {
+            Ok(_) => {
+                debug!("Stripped metadata from {:?} to {:?}", path, out);
+                Some(out)
+            },
+            Err(e) => { error!("Error writing {:?}, leaving {:?} out: {}", out, path, e); None },
+        }
+    }).collect()
+}
+
+/// Applies an EXIF orientation to the pixels
+fn upright(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Makes a new directory only this user can read in outdir for one request's outputs
+pub fn request_dir(outdir: &Path) -> Option<PathBuf> {
+    if let Err(e) = std::fs::create_dir_all(outdir) {
+        error!("Error creating {:?}: {}", outdir, e);
+        return None;
+    }
+    match tempfile::Builder::new().prefix(REQUEST_PREFIX).tempdir_in(outdir) {
+        Ok(dir) => Some(dir.keep()),
+        Err(e) => {
+            error!("Error creating an output dir in {:?}: {}", outdir, e);
+            None
+        },
+    }
+}
+
+/// Names outputs after the source file, adding a number when two files in one request share a name
+fn out_path(outdir: &Path, src: &Path, format: ImageFormat, used: &mut HashSet<PathBuf>) -> PathBuf {
+    let stem = src.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = format.extensions_str()[0];
+    let mut out = outdir.join(format!("{}.{}", stem, ext));
+    let mut i = 1;
+    while !used.insert(out.clone()) {
+        out = outdir.join(format!("{}_{}.{}", stem, i, ext));
+        i += 1;
+    }
+    out
+}
+
+/// Deletes the output dirs of earlier requests once apps have had time to read them.
+/// Anything else in outdir was put there by someone else and is left alone.
+pub fn clean(outdir: &Path, keep_hours: u64) {
+    let Some(cutoff) = SystemTime::now().checked_sub(Duration::from_secs(keep_hours * 3600)) else { return };
+    let Ok(rd) = std::fs::read_dir(outdir) else { return };
+    for entry in rd.filter_map(|e|e.ok()) {
+        let ours = entry.file_name().to_string_lossy().starts_with(REQUEST_PREFIX)
+            && entry.file_type().is_ok_and(|t| t.is_dir());
+        let old = entry.metadata().and_then(|m|m.modified()).is_ok_and(|t| t < cutoff);
+        if ours && old {
+            if let Err(e) = std::fs::remove_dir_all(entry.path()) {
+                warn!("Error removing old outputs {:?}: {}", entry.path(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+// The search box query language. Plain words are fuzzy matched against names and
+// descriptions like before, and field terms narrow the results down:
+//   ext:png,jpg  type:video  size:>5M  mtime:<7d  path:photos  desc:"red car"
+//   re:^IMG_\d+  color:teal  "exact phrase"  -excluded  -ext:gif
+use fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2};
+use regex::Regex;
+use std::path::Path;
+use crate::{FileIdx, filter::Kind, policy::parse_size, content::snippet, parse_color, lab, delta_e};
+
+/// Shown next to the search box
+pub const HELP: &str = "Words are fuzzy matched against names and descriptions. Terms can be combined:
+\"red car\"        exact phrase in the name or description
+-word            leave out matches, also works with the terms below
+ext:png,jpg      file extensions
+type:video       image, video, audio, document or dir
+size:>5M         file size with > < >= <=, at least without one
+mtime:<7d        modified within 7 days, >7d for older. Units are h, d, w and y
+path:photos      text anywhere in the full path
+desc:\"red car\"   text in the description, tags or comment
+re:^IMG_\\d+      regular expression on the file name
+color:teal       images with a color, by name or #hex";
+
+const FIELDS: [&str; 7] = ["ext", "type", "size", "mtime", "path", "desc", "re"];
+
+#[derive(Clone, Copy, Debug)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    /// Splits a leading comparison off a value, using dflt when there is none
+    fn split(v: &str, dflt: Cmp) -> (Cmp, &str) {
+        for (op, cmp) in [(">=", Cmp::Ge), ("<=", Cmp::Le), (">", Cmp::Gt), ("<", Cmp::Lt)] {
+            if let Some(rest) = v.strip_prefix(op) {
+                return (cmp, rest);
+            }
+        }
+        (dflt, v)
+    }
+
+    fn test<T: PartialOrd>(&self, a: T, b: T) -> bool {
+        match self {
+            Cmp::Lt => a < b,
+            Cmp::Le => a <= b,
+            Cmp::Gt => a > b,
+            Cmp::Ge => a >= b,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Term {
+    Ext(Vec<String>),
+    Type(Kind),
+    Size(Cmp, u64),
+    /// seconds since modification
+    Age(Cmp, f32),
+    Path(String),
+    Desc(String),
+    Regex(Regex),
+    Phrase(String),
+}
+
+impl Term {
+    /// None for unknown fields and for values that don't parse
+    fn parse(field: &str, v: &str) -> Option<Self> {
+        if v.is_empty() {
+            return None;
+        }
+        Some(match field {
+            "ext" => Term::Ext(v.split(',').filter(|e| !e.is_empty())
+                               .map(|e| e.trim_start_matches('.').to_lowercase()).collect()),
+            "type" => Term::Type(Kind::of_name(v)?),
+            "size" => {
+                let (cmp, v) = Cmp::split(v, Cmp::Ge);
+                Term::Size(cmp, parse_size(v)?)
+            },
+            "mtime" => {
+                let (cmp, v) = Cmp::split(v, Cmp::Lt);
+                let unit = match v.chars().last()? {
+                    'h' => 3600.0,
+                    'd' => 86400.0,
+                    'w' => 7.0 * 86400.0,
+                    'y' => 365.0 * 86400.0,
+                    c if c.is_ascii_digit() => 86400.0,
+                    _ => return None,
+                };
+                let num = v.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+                Term::Age(cmp, num.parse::<f32>().ok()? * unit)
+            },
+            "path" => Term::Path(v.to_lowercase()),
+            "desc" => Term::Desc(v.to_lowercase()),
+            "re" => Term::Regex(Regex::new(v).ok()?),
+            _ => return None,
+        })
+    }
+
+    fn matches(&self, item: &FileIdx, now: f32) -> bool {
+        let name = Path::new(&item.path).file_name().map_or(item.path.to_string(), |n| n.to_string_lossy().to_string());
+        let desc = || [item.text, item.tags, item.xattrs.as_deref()].into_iter().flatten()
+            .map(str::to_lowercase).collect::<Vec<_>>();
+        match self {
+            Term::Ext(exts) => !item.isdir && name.rsplit_once('.').is_some_and(|(_, ext)| exts.contains(&ext.to_lowercase())),
+            Term::Type(kind) => Kind::of(&item.path, item.isdir) == Some(*kind),
+            Term::Size(cmp, size) => !item.isdir && cmp.test(item.size, *size),
+            Term::Age(cmp, age) => cmp.test(now - item.mtime, *age),
+            Term::Path(txt) => item.path.to_lowercase().contains(txt),
+            Term::Desc(txt) => desc().iter().any(|d| d.contains(txt)),
+            Term::Regex(re) => re.is_match(&name),
+            Term::Phrase(txt) => name.to_lowercase().contains(txt) || desc().iter().any(|d| d.contains(txt)),
+        }
+    }
+}
+
+/// Splits on whitespace outside of double quotes, which are removed.
+/// The flag is whether the token had quotes.
+fn tokens(txt: &str) -> Vec<(String, bool)> {
+    let mut ret = vec![];
+    let mut cur = String::new();
+    let (mut quoted, mut in_quotes, mut started) = (false, false, false);
+    for c in txt.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+                started = true;
+            },
+            c if c.is_whitespace() && !in_quotes => {
+                if started {
+                    ret.push((std::mem::take(&mut cur), quoted));
+                }
+                (quoted, started) = (false, false);
+            },
+            c => {
+                cur.push(c);
+                started = true;
+            },
+        }
+    }
+    if started {
+        ret.push((cur, quoted));
+    }
+    ret
+}
+
+/// Where a result matched, shown with its label
+#[derive(Clone, Debug)]
+pub enum Hit {
+    /// char positions of the fuzzy match in the file name
+    Name(Vec<usize>),
+    /// the part of the description, tags or comment around the match, and all of it
+    Desc(String, String),
+    /// line number and text from a content search
+    Line(usize, String),
+}
+
+/// A parsed search term
+pub struct Query {
+    /// words to fuzzy match
+    text: String,
+    /// searched colors, left out when negated
+    colors: Vec<(bool, [f32; 3])>,
+    /// terms that must match, or must not when negated
+    terms: Vec<(bool, Term)>,
+    now: f32,
+}
+
+impl Query {
+    pub fn new(term: &str) -> Self {
+        let mut words = vec![];
+        let mut colors = vec![];
+        let mut terms = vec![];
+        for (token, quoted) in tokens(term) {
+            let (neg, body) = match token.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest),
+                _ => (false, token.as_str()),
+            };
+            match body.split_once(':') {
+                Some(("color", c)) => colors.extend(parse_color(c).map(|c|(neg, lab(c)))),
+                // a term that doesn't parse is left out since it's probably still being typed
+                Some((field, v)) if FIELDS.contains(&field) => terms.extend(Term::parse(field, v).map(|t|(neg, t))),
+                _ if neg || quoted => terms.push((neg, Term::Phrase(body.to_lowercase()))),
+                _ => words.push(token),
+            }
+        }
+        Self {
+            text: words.join(" "),
+            colors,
+            terms,
+            now: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs_f32(),
+        }
+    }
+
+    pub fn has_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    /// Higher is a better match, None doesn't match
+    pub fn score(&self, matcher: &SkimMatcherV2, item: &FileIdx, palette: Option<&Vec<[f32; 3]>>) -> Option<i64> {
+        if self.terms.iter().any(|(neg, term)| term.matches(item, self.now) == *neg) {
+            return None;
+        }
+        // closest palette entry for each searched color, within a distance that still looks alike
+        let mut color_score = 0;
+        for (neg, c) in self.colors.iter() {
+            let dist = palette.and_then(|palette| palette.iter().enumerate()
+                .map(|(rank, p)|delta_e(*c, *p) + rank as f32 * 2.0).min_by(|a, b|a.total_cmp(b)))
+                .filter(|dist| *dist <= 30.0);
+            match (neg, dist) {
+                (false, Some(dist)) => color_score += ((30.0 - dist) * 5.0) as i64,
+                (true, None) => {},
+                _ => return None,
+            }
+        }
+        if self.text.is_empty() {
+            return Some(color_score);
+        }
+        let term = self.text.as_str();
+        let path = Path::new(item.path.as_str());
+        let name_match = matcher.fuzzy_match(match path.file_name() {
+            Some(p) => p.to_string_lossy(),
+            None => path.to_string_lossy(),
+        }.as_ref(), term);
+        let sem_match = match item.text {
+            Some(text) => matcher.fuzzy_match(text, term),
+            None => None,
+        };
+        let tag_match = match item.tags {
+            Some(tags) => matcher.fuzzy_match(tags, term),
+            None => None,
+        };
+        let attr_match = match item.xattrs {
+            Some(ref attrs) => matcher.fuzzy_match(attrs, term),
+            None => None,
+        };
+        let sem_match = sem_match.max(tag_match).max(attr_match);
+        match (name_match, sem_match) {
+            (Some(a), Some(b)) => Some(a.max(b) + color_score),
+            (Some(a), None) => Some(a + color_score),
+            (None, Some(b)) => Some(b + color_score),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether the words matched the name or the description, like score decides.
+    /// None when there are no words, since field terms aren't highlighted.
+    pub fn hit(&self, matcher: &SkimMatcherV2, item: &FileIdx) -> Option<Hit> {
+        if self.text.is_empty() {
+            return None;
+        }
+        let term = self.text.as_str();
+        let path = Path::new(item.path.as_str());
+        let name_match = matcher.fuzzy_indices(match path.file_name() {
+            Some(p) => p.to_string_lossy(),
+            None => path.to_string_lossy(),
+        }.as_ref(), term);
+        let sem_match = [item.text, item.tags, item.xattrs.as_deref()].into_iter().flatten()
+            .filter_map(|desc| Some((matcher.fuzzy_match(desc, term)?, desc))).max_by_key(|(score, _)| *score);
+        match (name_match, sem_match) {
+            (Some((a, indices)), Some((b, _))) if a >= b => Some(Hit::Name(indices)),
+            (Some((_, indices)), None) => Some(Hit::Name(indices)),
+            (_, Some((_, desc))) => {
+                let first = matcher.fuzzy_indices(desc, term).and_then(|(_, i)| i.first().copied()).unwrap_or(0);
+                Some(Hit::Desc(snippet(desc, first, 40), desc.to_string()))
+            },
+            (None, None) => None,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Paths don't have to be UTF-8, but the picker keeps them in Strings. Bytes that
+// aren't valid UTF-8 are stored as the chars U+EF80..U+EFFF, one per byte, the way
+// OPTU-8 does, and turned back into bytes whenever a path goes to the filesystem.
+// Those chars in a real name are stored byte by byte too, so the round trip is exact.
+// The portal only writes history paths in this form, so it doesn't use all of this.
+#![allow(dead_code)]
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+const RAW: u32 = 0xEF00;
+
+fn is_raw(c: char) -> bool {
+    (RAW + 0x80..=RAW + 0xFF).contains(&(c as u32))
+}
+
+fn push_raw(out: &mut String, bytes: &[u8]) {
+    bytes.iter().for_each(|b| out.push(char::from_u32(RAW + *b as u32).unwrap()));
+}
+
+/// The String form of a path, which to_path turns back into the same bytes
+pub fn to_str(path: impl AsRef<OsStr>) -> String {
+    let bytes = path.as_ref().as_bytes();
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        if !s.chars().any(is_raw) {
+            return s.to_string();
+        }
+    }
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match is_raw(c) {
+                true => push_raw(&mut out, c.to_string().as_bytes()),
+                false => out.push(c),
+            }
+        }
+        push_raw(&mut out, chunk.invalid());
+    }
+    out
+}
+
+/// The bytes of a path stored by to_str
+pub fn to_bytes(s: &str) -> Cow<'_, [u8]> {
+    if !s.chars().any(is_raw) {
+        return Cow::Borrowed(s.as_bytes());
+    }
+    let mut out = Vec::with_capacity(s.len());
+    let mut buf = [0; 4];
+    for c in s.chars() {
+        match is_raw(c) {
+            true => out.push((c as u32 - RAW) as u8),
+            false => out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// The path stored by to_str
+pub fn to_path(s: &str) -> Cow<'_, Path> {
+    match to_bytes(s) {
+        Cow::Borrowed(_) => Cow::Borrowed(Path::new(s)),
+        Cow::Owned(bytes) => Cow::Owned(PathBuf::from(std::ffi::OsString::from_vec(bytes))),
+    }
+}
@@ -0,0 +1,110 @@
+// Searches saved from the search bar, kept in the [SavedSearches] section of
+// pikeru.conf and listed under the bookmarks. Each line holds the dirs separated
+// by colons, the options, and the query:
+//   Screenshots = /home/a/Pictures | recursive sort:age_desc kind:image max_age:7 | screenshot
+// Colons, bars and backslashes in a dir are escaped with a backslash.
+use std::{fmt, mem};
+use crate::{SORTS, filter::Filter};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedSearch {
+    pub label: String,
+    pub dirs: Vec<String>,
+    pub query: String,
+    pub recursive: bool,
+    /// search inside text files instead of names
+    pub content: bool,
+    pub sort_by: i32,
+    pub filter: Filter,
+}
+
+impl SavedSearch {
+    pub fn parse(label: &str, txt: &str) -> Result<Self, String> {
+        let (dirs, rest) = split_dirs(txt);
+        let mut parts = rest.unwrap_or_default().splitn(2, '|').map(str::trim);
+        let (opts, query) = match (parts.next(), parts.next()) {
+            (Some(opts), Some(query)) if !dirs.is_empty() => (opts, query),
+            _ => return Err("expected DIRS | OPTIONS | QUERY".to_string()),
+        };
+        let mut ret = SavedSearch {
+            label: label.to_string(),
+            dirs,
+            query: query.to_string(),
+            recursive: false,
+            content: false,
+            sort_by: 1,
+            filter: Filter::default(),
+        };
+        // what isn't a search option is a filter word
+        let mut filter_words = vec![];
+        for word in opts.split_whitespace() {
+            match word.split_once(':') {
+                None if word == "recursive" => ret.recursive = true,
+                None if word == "content" => ret.content = true,
+                Some(("sort", v)) => ret.sort_by = SORTS.iter().position(|s| *s == v)
+                    .ok_or(format!("unknown sort {}", v))? as i32 + 1,
+                _ => filter_words.push(word),
+            }
+        }
+        ret.filter = Filter::parse(&filter_words.join(" "))?;
+        Ok(ret)
+    }
+}
+
+impl fmt::Display for SavedSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut opts = vec![];
+        if self.recursive {
+            opts.push("recursive".to_string());
+        }
+        if self.content {
+            opts.push("content".to_string());
+        }
+        opts.push(format!("sort:{}", SORTS[self.sort_by as usize - 1]));
+        if !self.filter.is_empty() {
+            opts.push(self.filter.to_string());
+        }
+        write!(f, "{} | {} | {}", self.dirs.iter().map(|dir| escape(dir)).collect::<Vec<_>>().join(":"), opts.join(" "), self.query)
+    }
+}
+
+fn escape(dir: &str) -> String {
+    dir.replace('\\', "\\\\").replace(':', "\\:").replace('|', "\\|")
+}
+
+/// Splits the dirs at unescaped colons up to the first unescaped bar, which starts the rest of the line
+fn split_dirs(txt: &str) -> (Vec<String>, Option<&str>) {
+    let mut dirs = vec![];
+    let mut dir = String::new();
+    let mut rest = None;
+    let mut chars = txt.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => dir.extend(chars.next().map(|(_, c)| c)),
+            ':' => dirs.push(mem::take(&mut dir)),
+            '|' => { rest = Some(&txt[i + 1..]); break },
+            _ => dir.push(c),
+        }
+    }
+    dirs.push(dir);
+    let dirs = dirs.iter().map(|dir| dir.trim()).filter(|dir| !dir.is_empty()).map(str::to_string).collect();
+    (dirs, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let saved = SavedSearch::parse("shots", r"/home/a/Pictures:/mnt/c\:/odd\|dir\\ | recursive sort:age_desc kind:image max_age:7 | screenshot").unwrap();
+        assert_eq!(saved.dirs, ["/home/a/Pictures", "/mnt/c:/odd|dir\\"]);
+        assert!(saved.recursive && !saved.content);
+        assert_eq!(saved.query, "screenshot");
+        assert_eq!(SavedSearch::parse("shots", &saved.to_string()).unwrap(), saved);
+
+        let saved = SavedSearch { query: "a | b: c".to_string(), content: true, ..saved };
+        assert_eq!(SavedSearch::parse("shots", &saved.to_string()).unwrap(), saved);
+        assert!(SavedSearch::parse("shots", "/home/a | recursive").is_err());
+    }
+}
@@ -0,0 +1,15 @@
+// file:// URIs for paths handed to other programs. Shared by the picker and the portal.
+use std::{os::unix::ffi::OsStrExt, path::Path};
+
+/// Percent encodes everything but unreserved characters, so any path bytes make a valid URI
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
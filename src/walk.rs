@@ -0,0 +1,70 @@
+// Directory walks for recursive, content and 'pikeru search' searches. The
+// [SearchIgnore] section, .gitignore files and --allow/--deny decide what's skipped,
+// and the search_depth, follow_symlinks and cross_filesystems settings how far it goes.
+use std::{path::PathBuf, sync::Arc};
+use ignore::{gitignore, Match, WalkBuilder, WalkParallel};
+use crate::{Policy, rawpath};
+
+#[derive(Clone)]
+pub struct WalkOpts {
+    pub gitignore: String,
+    pub respect_gitignore: bool,
+    /// levels of subdirectories searched, all of them when None
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    pub cross_filesystems: bool,
+    pub policy: Option<Arc<Policy>>,
+}
+
+impl WalkOpts {
+    /// Visits the entries of dirs, and everything below them when recursive,
+    /// until visit returns false
+    pub fn walk(&self, dirs: &[String], recursive: bool, visit: &mut impl FnMut(PathBuf) -> bool) {
+        let Some(builder) = self.builder(dirs, recursive) else { return };
+        for entry in builder.build() {
+            match entry {
+                Ok(entry) if entry.depth() == 0 => {},
+                Ok(entry) => if !visit(entry.into_path()) {
+                    return;
+                },
+                Err(e) => eprintln!("Error reading dir: {}", e),
+            }
+        }
+    }
+
+    /// A walk of everything below dirs with a thread per core.
+    /// Depth 0 entries are the dirs themselves and depth 1 their entries.
+    pub fn parallel(&self, dirs: &[String]) -> Option<WalkParallel> {
+        let mut builder = self.builder(dirs, true)?;
+        builder.threads(num_cpus::get());
+        Some(builder.build_parallel())
+    }
+
+    fn builder(&self, dirs: &[String], recursive: bool) -> Option<WalkBuilder> {
+        let (first, rest) = dirs.split_first()?;
+        let mut builder = WalkBuilder::new(rawpath::to_path(first));
+        rest.iter().for_each(|dir| { builder.add(rawpath::to_path(dir)); });
+        let top_ignore = build_ignore(&self.gitignore);
+        let policy = self.policy.clone();
+        // only .gitignore files inside the searched dirs count, like before there was a walker
+        builder.standard_filters(false)
+            .git_ignore(self.respect_gitignore)
+            .require_git(false)
+            .follow_links(self.follow_symlinks)
+            .same_file_system(!self.cross_filesystems)
+            .max_depth(if recursive { self.max_depth.map(|d| d + 1) } else { Some(1) })
+            .filter_entry(move |entry| {
+                let isdir = entry.file_type().is_some_and(|t| t.is_dir());
+                entry.depth() == 0 || !(matches!(top_ignore.matched(entry.path(), isdir), Match::Ignore(_))
+                    || policy.as_ref().is_some_and(|p| !p.allows(entry.path(), true)))
+            });
+        Some(builder)
+    }
+}
+
+/// Rules from the [SearchIgnore] section of pikeru.conf
+fn build_ignore(gitignore_txt: &str) -> gitignore::Gitignore {
+    let mut ig_builder = gitignore::GitignoreBuilder::new("");
+    gitignore_txt.lines().for_each(|line|{ig_builder.add_line(None, line).unwrap();});
+    ig_builder.build().unwrap()
+}
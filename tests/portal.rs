@@ -0,0 +1,522 @@
+// Runs the portal binary on a private D-Bus session with a temp HOME, a fake
+// picker script and a fake indexer command, and drives it through zbus proxies.
+// Tests are skipped when dbus-daemon isn't installed.
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    io::{BufRead, BufReader},
+    os::unix::{ffi::{OsStrExt, OsStringExt}, fs::PermissionsExt},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+use zbus::{proxy, zvariant::{ObjectPath, OwnedValue, Value}};
+
+const SERVICE: &str = "org.freedesktop.impl.portal.desktop.pikeru";
+
+#[proxy(
+    interface = "org.freedesktop.impl.portal.FileChooser",
+    default_service = "org.freedesktop.impl.portal.desktop.pikeru",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait FileChooser {
+    fn open_file(&self, handle: ObjectPath<'_>, app_id: &str, parent: &str, title: &str,
+                 options: HashMap<&str, Value<'_>>) -> zbus::Result<(u32, HashMap<String, OwnedValue>)>;
+    fn save_file(&self, handle: ObjectPath<'_>, app_id: &str, parent: &str, title: &str,
+                 options: HashMap<&str, Value<'_>>) -> zbus::Result<(u32, HashMap<String, OwnedValue>)>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.impl.portal.SearchIndexer",
+    default_service = "org.freedesktop.impl.portal.desktop.pikeru",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait SearchIndexer {
+    fn update(&self, dirs: &[&str]) -> zbus::Result<()>;
+    fn pause_resume(&self, active: bool) -> zbus::Result<()>;
+    fn configure(&self, respect_gitignore: bool, ignore: &str) -> zbus::Result<()>;
+}
+
+/// A dbus-daemon and portal running against their own temp dir, stopped on drop
+struct Session {
+    dir: PathBuf,
+    dbus: Child,
+    portal: Child,
+    conn: zbus::Connection,
+}
+
+impl Session {
+    /// Returns None when there's no dbus-daemon to run the tests with.
+    /// {dir} in extra_config is replaced with the session's temp dir.
+    async fn start(name: &str, extra_config: &str) -> Option<Self> {
+        let dir = std::env::temp_dir().join(format!("pikeru-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("files")).unwrap();
+        script(&dir.join("picker.sh"), &format!(
+            "printf '%s\\n' \"$@\" > '{0}/picker_args'\n[ -r '{0}/answer' ] && cat '{0}/answer'\nexit 0",
+            dir.display()));
+        // stands in for pikeru when the real wrapper script is the picker
+        script(&dir.join("pikeru.sh"), &format!(
+            "printf '%s\\n' \"$@\" > '{0}/pikeru_args'\n[ -r '{0}/answer' ] && cat '{0}/answer'\nexit 0",
+            dir.display()));
+        script(&dir.join("describe.sh"), "echo \"contents: $(cat \"$1\")\"");
+        script(&dir.join("crash.sh"), "echo 'picker broke' >&2\nkill -SEGV $$");
+        script(&dir.join("hang.sh"), &format!("sleep 30 &\necho $! > '{}/hang_pid'\nwait", dir.display()));
+        std::fs::write(dir.join("config"), format!(
+            "log_level = debug\n[filepicker]\ncmd = {0}/picker.sh\ndefault_save_dir = {0}/files\npostprocess_dir = {0}/postprocess\n\
+             [indexer]\nenable = true\ncmd = {0}/describe.sh\ncheck = true\nextensions = txt\n{1}",
+            dir.display(), extra_config.replace("{dir}", &dir.to_string_lossy()))).unwrap();
+
+        let mut dbus = match Command::new("dbus-daemon").args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Skipping portal test, can't run dbus-daemon: {}", e);
+                return None;
+            },
+        };
+        let mut address = String::new();
+        BufReader::new(dbus.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        let address = address.trim().to_string();
+        let log = std::fs::File::create(dir.join("portal.log")).unwrap();
+        let portal = Command::new(env!("CARGO_BIN_EXE_portal"))
+            .args(["-c", &dir.join("config").to_string_lossy()])
+            .env("HOME", &dir)
+            .env("DBUS_SESSION_BUS_ADDRESS", &address)
+            .env("PIKERU", dir.join("pikeru.sh"))
+            .current_dir(&dir)
+            .stdout(log.try_clone().unwrap())
+            .stderr(log)
+            .spawn().unwrap();
+        let conn = zbus::connection::Builder::address(address.as_str()).unwrap().build().await.unwrap();
+        let mut session = Self { dir, dbus, portal, conn };
+        session.wait_for_name().await;
+        Some(session)
+    }
+
+    async fn wait_for_name(&mut self) {
+        let dbus = zbus::fdo::DBusProxy::new(&self.conn).await.unwrap();
+        let start = Instant::now();
+        while !dbus.name_has_owner(SERVICE.try_into().unwrap()).await.unwrap() {
+            assert!(self.portal.try_wait().unwrap().is_none(), "portal exited early");
+            assert!(start.elapsed() < Duration::from_secs(20), "portal didn't take its name");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    async fn chooser(&self) -> FileChooserProxy<'_> {
+        FileChooserProxy::new(&self.conn).await.unwrap()
+    }
+
+    async fn indexer(&self) -> SearchIndexerProxy<'_> {
+        SearchIndexerProxy::new(&self.conn).await.unwrap()
+    }
+
+    /// Makes a file under the session's files dir and returns its path
+    fn file(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.dir.join("files").join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Sets the paths the fake picker prints
+    fn answer(&self, paths: &[&Path]) {
+        let txt = paths.iter().flat_map(|p| [p.as_os_str().as_bytes(), b"\n"].concat()).collect::<Vec<u8>>();
+        std::fs::write(self.dir.join("answer"), txt).unwrap();
+    }
+
+    /// The arguments the portal ran the picker with, byte for byte
+    fn picker_args(&self) -> Vec<OsString> {
+        self.args("picker_args")
+    }
+
+    /// The arguments the wrapper script ran pikeru with
+    fn pikeru_args(&self) -> Vec<OsString> {
+        self.args("pikeru_args")
+    }
+
+    fn args(&self, file: &str) -> Vec<OsString> {
+        let txt = std::fs::read(self.dir.join(file)).unwrap();
+        txt.split(|b| *b == b'\n').filter(|l| !l.is_empty()).map(|l| OsString::from_vec(l.to_vec())).collect()
+    }
+
+    fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join("portal.log")).unwrap_or_default()
+    }
+
+    fn db(&self) -> rusqlite::Connection {
+        rusqlite::Connection::open(self.dir.join(".cache").join("pikeru").join("index.db")).unwrap()
+    }
+
+    fn description(&self, fname: &str) -> Option<String> {
+        self.db().query_row("select description from descriptions where fname = ?1", [fname], |row| row.get(0)).ok()
+    }
+
+    /// Waits up to a few seconds for the indexer to describe a file
+    async fn wait_for_description(&self, fname: &str) -> Option<String> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(10) {
+            if let Some(desc) = self.description(fname) {
+                return Some(desc);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        None
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.portal.kill();
+        let _ = self.portal.wait();
+        let _ = self.dbus.kill();
+        let _ = self.dbus.wait();
+        if std::thread::panicking() {
+            eprintln!("portal log:\n{}", std::fs::read_to_string(self.dir.join("portal.log")).unwrap_or_default());
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn script(path: &Path, body: &str) {
+    std::fs::write(path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+fn handle() -> ObjectPath<'static> {
+    ObjectPath::try_from("/org/freedesktop/portal/desktop/request/1_1/t").unwrap()
+}
+
+fn uris(results: &HashMap<String, OwnedValue>) -> Vec<String> {
+    results.get("uris").map(|v| Vec::<String>::try_from(v.try_clone().unwrap()).unwrap()).unwrap_or_default()
+}
+
+/// Like a directory path from an app: a nul terminated byte array
+fn bytes(path: &Path) -> Value<'static> {
+    let mut b = path.as_os_str().as_bytes().to_vec();
+    b.push(0);
+    Value::from(b)
+}
+
+#[tokio::test]
+async fn open_file_returns_uris_and_records_history() {
+    let Some(s) = Session::start("open", "").await else { return };
+    let a = s.file("a.txt", "a");
+    let b = s.file("b.txt", "b");
+    // latin-1, not UTF-8
+    let c = s.dir.join("files").join(OsStr::from_bytes(b"caf\xe9.txt"));
+    std::fs::write(&c, "c").unwrap();
+    s.answer(&[&a, &b, &c]);
+    let opts = HashMap::from([("multiple", Value::from(true))]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", opts).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(uris(&results), vec![format!("file://{}", a.display()), format!("file://{}", b.display()),
+                                    format!("file://{}/files/caf%E9.txt", s.dir.display())]);
+    // multiple, directory, save
+    assert_eq!(s.picker_args()[..3], ["1", "0", "0"]);
+    let (app_id, mode, paths): (String, String, String) = s.db()
+        .query_row("select app_id, mode, paths from history", [], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))).unwrap();
+    assert_eq!((app_id.as_str(), mode.as_str()), ("org.test.app", "open_multiple"));
+    assert!(paths.starts_with(&format!("{}\n{}\n", a.display(), b.display())), "{}", paths);
+    assert!(!paths.contains('\u{fffd}'), "{}", paths);
+    // pikeru history prints the name as it is on disk
+    let out = Command::new(env!("CARGO_BIN_EXE_pikeru")).args(["history", "org.test.app"]).env("HOME", &s.dir).output().unwrap();
+    let line = [b"    ", c.as_os_str().as_bytes(), b"\n"].concat();
+    assert!(out.stdout.windows(line.len()).any(|w| w == line), "{}", String::from_utf8_lossy(&out.stdout));
+}
+
+#[tokio::test]
+async fn open_file_cancelled() {
+    let Some(s) = Session::start("cancel", "").await else { return };
+    let opts = HashMap::from([("directory", Value::from(true))]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", opts).await.unwrap();
+    assert_eq!(code, 1);
+    assert!(results.is_empty());
+    assert_eq!(s.picker_args()[..3], ["0", "1", "0"]);
+    let count: i64 = s.db().query_row("select count(*) from history", [], |r| r.get(0)).unwrap();
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+async fn open_multiple_directories() {
+    let Some(s) = Session::start("dirs", "").await else { return };
+    let (a, b) = (s.dir.join("files").join("a"), s.dir.join("files").join("b"));
+    std::fs::create_dir_all(&a).unwrap();
+    std::fs::create_dir_all(&b).unwrap();
+    s.answer(&[&a, &b]);
+    let opts = HashMap::from([("directory", Value::from(true)), ("multiple", Value::from(true))]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", opts).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(uris(&results).len(), 2);
+    assert_eq!(s.picker_args()[..3], ["1", "1", "0"]);
+    let mode: String = s.db().query_row("select mode from history", [], |r| r.get(0)).unwrap();
+    assert_eq!(mode, "directories");
+}
+
+#[tokio::test]
+async fn policy_blocks_paths_outside_allowed_dirs() {
+    let config = "[policy.org.test.app]\nallow = ~/files/it's allowed\ndeny = *.pem\nmax_size = 1K\n";
+    let Some(s) = Session::start("policy", config).await else { return };
+    let inside = s.file("it's allowed/in.txt", "in");
+    let outside = s.file("out.txt", "out");
+    s.answer(&[&inside, &outside]);
+    let opts = HashMap::from([("multiple", Value::from(true))]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", opts).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(uris(&results), vec![format!("file://{}/files/it%27s%20allowed/in.txt", s.dir.display())]);
+    // each option is its own argument, so nothing in them needs quoting
+    let allowed = s.dir.join("files").join("it's allowed");
+    assert_eq!(s.picker_args()[4..], [OsStr::new("--allow"), allowed.as_os_str(), OsStr::new("--deny"), OsStr::new("*.pem"),
+                                      OsStr::new("--max-size"), OsStr::new("1024")]);
+}
+
+#[tokio::test]
+async fn save_file_suggests_folder_and_name() {
+    let Some(s) = Session::start("save", "").await else { return };
+    let folder = s.dir.join("files").join("saves");
+    std::fs::create_dir_all(&folder).unwrap();
+    let target = folder.join("page.html");
+    s.answer(&[&target]);
+    let opts = HashMap::from([("current_folder", bytes(&folder)), ("current_name", Value::from("page.html"))]);
+    let (code, results) = s.chooser().await.save_file(handle(), "org.test.app", "", "Save", opts).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(uris(&results), vec![format!("file://{}", target.display())]);
+    let args = s.picker_args();
+    assert_eq!(args[..3], ["0", "0", "1"]);
+    assert_eq!(args[3], target.as_os_str());
+}
+
+#[tokio::test]
+async fn save_file_non_utf8_folder() {
+    let Some(s) = Session::start("latin1", "").await else { return };
+    // "café" in latin-1, which isn't valid UTF-8
+    let folder = s.dir.join("files").join(OsStr::from_bytes(b"caf\xe9"));
+    std::fs::create_dir_all(&folder).unwrap();
+    let target = folder.join("a b.txt");
+    s.answer(&[&target]);
+    let opts = HashMap::from([("current_folder", bytes(&folder)), ("current_name", Value::from("a b.txt"))]);
+    let (code, results) = s.chooser().await.save_file(handle(), "org.test.app", "", "Save", opts).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(s.picker_args()[3], target.as_os_str());
+    assert_eq!(uris(&results), vec![format!("file://{}/files/caf%E9/a%20b.txt", s.dir.display())]);
+}
+
+#[tokio::test]
+async fn save_file_preselects_current_file() {
+    let Some(s) = Session::start("currentfile", "").await else { return };
+    let existing = s.file("Überblick 写真.png", "old");
+    let opts = HashMap::from([
+        ("current_file", bytes(&existing)),
+        ("current_folder", bytes(&s.dir)),
+        ("current_name", Value::from("ignored.png")),
+    ]);
+    let (code, _) = s.chooser().await.save_file(handle(), "org.test.app", "", "Save", opts).await.unwrap();
+    assert_eq!(code, 1);
+    assert_eq!(s.picker_args()[3], existing.as_os_str());
+}
+
+#[tokio::test]
+async fn open_file_starts_in_current_folder() {
+    let Some(s) = Session::start("openfolder", "").await else { return };
+    let folder = s.dir.join("files").join("Fotos ü 写真");
+    let photo = s.file("Fotos ü 写真/straße.jpg", "jpg");
+    s.answer(&[&photo]);
+    let opts = HashMap::from([("current_folder", bytes(&folder))]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", opts).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(s.picker_args()[3], folder.as_os_str());
+    let expected = format!("file://{}/files/Fotos%20%C3%BC%20%E5%86%99%E7%9C%9F/stra%C3%9Fe.jpg", s.dir.display());
+    assert_eq!(uris(&results), vec![expected]);
+    // without current_folder the next request starts where this one ended
+    s.chooser().await.open_file(handle(), "org.test.app", "", "Open", HashMap::new()).await.unwrap();
+    assert_eq!(s.picker_args()[3], folder.as_os_str());
+}
+
+#[tokio::test]
+async fn wrapper_passes_folder_unchanged() {
+    let config = format!("[filepicker]\ncmd = {}/xdg_portal/pikeru-wrapper.sh\n", env!("CARGO_MANIFEST_DIR"));
+    let Some(s) = Session::start("wrapper", &config).await else { return };
+    // latin-1 bytes and shell syntax that eval would have run
+    let folder = s.dir.join("files").join(OsStr::from_bytes(b"caf\xe9 \"$(touch pwned)\" `touch pwned`"));
+    std::fs::create_dir_all(&folder).unwrap();
+    let photo = folder.join("a.jpg");
+    std::fs::write(&photo, "jpg").unwrap();
+    s.answer(&[&photo]);
+    let opts = HashMap::from([("current_folder", bytes(&folder))]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", opts).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(s.pikeru_args(), [OsStr::new("-m"), OsStr::new("file"), OsStr::new("-t"), OsStr::new("File Picker"),
+                                 OsStr::new("-p"), folder.as_os_str()]);
+    assert!(!s.dir.join("pwned").exists());
+    assert_eq!(uris(&results), vec![format!("file://{}/files/caf%E9%20%22%24%28touch%20pwned%29%22%20%60touch%20pwned%60/a.jpg",
+                                            s.dir.display())]);
+}
+
+#[tokio::test]
+async fn save_file_defaults_to_save_dir() {
+    let Some(s) = Session::start("savedefault", "").await else { return };
+    let (code, _) = s.chooser().await.save_file(handle(), "org.test.app", "", "Save", HashMap::new()).await.unwrap();
+    assert_eq!(code, 1);
+    assert_eq!(s.picker_args()[3], s.dir.join("files").join("download").as_os_str());
+}
+
+#[tokio::test]
+async fn update_stores_descriptions() {
+    let Some(s) = Session::start("index", "").await else { return };
+    s.file("notes.txt", "hello");
+    s.file("skipped.bin", "data");
+    let dir = s.dir.join("files");
+    s.indexer().await.update(&[&dir.to_string_lossy()]).await.unwrap();
+    assert_eq!(s.wait_for_description("notes.txt").await.as_deref().map(str::trim), Some("contents: hello"));
+    assert_eq!(s.description("skipped.bin"), None);
+}
+
+#[tokio::test]
+async fn configure_ignores_files() {
+    let Some(s) = Session::start("ignore", "").await else { return };
+    s.file("secret.txt", "hidden");
+    s.file("public.txt", "shown");
+    let dir = s.dir.join("files");
+    let indexer = s.indexer().await;
+    indexer.configure(true, "secret.txt").await.unwrap();
+    indexer.update(&[&dir.to_string_lossy()]).await.unwrap();
+    assert!(s.wait_for_description("public.txt").await.is_some());
+    assert_eq!(s.description("secret.txt"), None);
+}
+
+#[tokio::test]
+async fn pause_resume_holds_indexing() {
+    let Some(s) = Session::start("pause", "").await else { return };
+    s.file("later.txt", "later");
+    let dir = s.dir.join("files");
+    let indexer = s.indexer().await;
+    indexer.pause_resume(false).await.unwrap();
+    indexer.update(&[&dir.to_string_lossy()]).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(s.description("later.txt"), None);
+    indexer.pause_resume(true).await.unwrap();
+    assert!(s.wait_for_description("later.txt").await.is_some());
+}
+
+#[tokio::test]
+async fn crashed_picker_falls_back() {
+    let config = "[filepicker]\ncmd = {dir}/crash.sh\nfallback_cmd = {dir}/picker.sh\nnotify = false\n";
+    let Some(s) = Session::start("fallback", config).await else { return };
+    let a = s.file("a.txt", "a");
+    s.answer(&[&a]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", HashMap::new()).await.unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(uris(&results), vec![format!("file://{}", a.display())]);
+}
+
+#[tokio::test]
+async fn crashed_picker_is_reported() {
+    let Some(s) = Session::start("crash", "[filepicker]\ncmd = {dir}/crash.sh\nnotify = false\n").await else { return };
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", HashMap::new()).await.unwrap();
+    assert_eq!(code, 1);
+    assert!(results.is_empty());
+    let log = s.log();
+    assert!(log.contains("killed by signal 11"), "{}", log);
+    assert!(log.contains("picker broke"), "{}", log);
+}
+
+#[tokio::test]
+async fn hung_picker_times_out() {
+    let Some(s) = Session::start("timeout", "[filepicker]\ncmd = {dir}/hang.sh\ntimeout = 1\nnotify = false\n").await else { return };
+    let start = Instant::now();
+    let (code, _) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", HashMap::new()).await.unwrap();
+    assert_eq!(code, 1);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    // the whole process group was closed, not just the shell the portal started
+    let pid = std::fs::read_to_string(s.dir.join("hang_pid")).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+    assert!(stat.is_empty() || stat.contains(") Z "), "{}", stat);
+}
+
+#[tokio::test]
+async fn reload_while_picker_open() {
+    let Some(s) = Session::start("reload_open", "[filepicker]\ncmd = {dir}/hang.sh\nnotify = false\n").await else { return };
+    let a = s.file("a.txt", "a");
+    s.answer(&[&a]);
+    let chooser = s.chooser().await;
+    let hung = chooser.open_file(handle(), "org.test.app", "", "Open", HashMap::new());
+    let second = async {
+        while !s.dir.join("hang_pid").exists() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let config = std::fs::read_to_string(s.dir.join("config")).unwrap();
+        std::fs::write(s.dir.join("config"), config.replace("hang.sh", "picker.sh")).unwrap();
+        while !s.log().contains("Reloaded config") {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        tokio::time::timeout(Duration::from_secs(10),
+            chooser.open_file(handle(), "org.test.app", "", "Open", HashMap::new())).await
+    };
+    let result = tokio::select! {
+        _ = hung => panic!("the hung picker returned"),
+        result = second => result,
+    };
+    let pid = std::fs::read_to_string(s.dir.join("hang_pid")).unwrap();
+    let _ = Command::new("kill").arg(pid.trim()).status();
+    let (code, results) = result.expect("a reload while a picker was open blocked new requests").unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(uris(&results), vec![format!("file://{}", a.display())]);
+}
+
+#[tokio::test]
+async fn postprocess_outputs_get_their_own_dir() {
+    let Some(s) = Session::start("postprocess", "[postprocess]\next:png = format:jpg\n").await else { return };
+    let pic = s.dir.join("files").join("pic.png");
+    img::RgbImage::new(8, 8).save(&pic).unwrap();
+    let outdir = s.dir.join("postprocess");
+    let week_ago = std::time::SystemTime::now() - Duration::from_secs(7 * 24 * 3600);
+    // someone else's file and an expired request's outputs
+    std::fs::create_dir_all(outdir.join("request-old")).unwrap();
+    std::fs::write(outdir.join("mine.jpg"), "mine").unwrap();
+    for old in ["request-old", "mine.jpg"] {
+        std::fs::File::open(outdir.join(old)).unwrap().set_modified(week_ago).unwrap();
+    }
+    s.answer(&[&pic]);
+    let mut outputs = vec![];
+    for _ in 0..2 {
+        let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", HashMap::new()).await.unwrap();
+        assert_eq!(code, 0);
+        let uri = uris(&results).pop().unwrap();
+        outputs.push(PathBuf::from(uri.strip_prefix("file://").unwrap()));
+    }
+    assert_ne!(outputs[0], outputs[1]);
+    for out in &outputs {
+        assert_eq!(out.file_name().unwrap(), "pic.jpg");
+        assert!(out.parent().unwrap().starts_with(&outdir) && out.parent().unwrap() != outdir, "{:?}", out);
+        assert!(out.is_file());
+    }
+    assert!(!outdir.join("request-old").exists());
+    assert!(outdir.join("mine.jpg").exists());
+}
+
+#[tokio::test]
+async fn postprocessor_output_is_checked() {
+    let config = "[filepicker]\npostprocessor = {dir}/pp.sh\n[policy.org.test.app]\nallow = ~/files\n";
+    let Some(s) = Session::start("postprocessor", config).await else { return };
+    script(&s.dir.join("pp.sh"), &format!(
+        "while read f; do cp \"$f\" \"$POSTPROCESS_DIR/copy.txt\"; echo \"$POSTPROCESS_DIR/copy.txt\"; done\necho '{}/secret.txt'",
+        s.dir.display()));
+    std::fs::write(s.dir.join("secret.txt"), "secret").unwrap();
+    let picked = s.file("a.txt", "a");
+    // a denied file in postprocess_dir isn't trusted just for being there
+    let planted = s.dir.join("postprocess").join("planted.txt");
+    std::fs::create_dir_all(planted.parent().unwrap()).unwrap();
+    std::fs::write(&planted, "planted").unwrap();
+    s.answer(&[&planted, &picked]);
+    let opts = HashMap::from([("multiple", Value::from(true))]);
+    let (code, results) = s.chooser().await.open_file(handle(), "org.test.app", "", "Open", opts).await.unwrap();
+    assert_eq!(code, 0);
+    let uris = uris(&results);
+    assert_eq!(uris.len(), 1, "{:?}", uris);
+    let out = PathBuf::from(uris[0].strip_prefix("file://").unwrap());
+    assert_eq!(out.file_name().unwrap(), "copy.txt");
+    assert_ne!(out.parent().unwrap(), s.dir.join("postprocess"));
+    assert_eq!(std::fs::read_to_string(out).unwrap(), "a");
+}
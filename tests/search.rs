@@ -0,0 +1,214 @@
+// Runs 'pikeru search' against a temp HOME and file tree, so ranking can be
+// checked without a display.
+use std::{
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// A temp dir with a HOME holding pikeru.conf and a tree of files to search
+struct Tree {
+    dir: PathBuf,
+}
+
+impl Tree {
+    fn new(name: &str, files: &[&str]) -> Self {
+        let dir = std::env::temp_dir().join(format!("pikeru-search-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("home").join(".config")).unwrap();
+        std::fs::write(dir.join("home").join(".config").join("pikeru.conf"),
+                       "[SearchIgnore]\n.git/\nbuild/\n").unwrap();
+        for file in files {
+            let path = dir.join("files").join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        Self { dir }
+    }
+
+    /// Replaces pikeru.conf with one that has these [Settings] lines
+    fn settings(&self, settings: &str) {
+        std::fs::write(self.dir.join("home").join(".config").join("pikeru.conf"),
+                       format!("[Settings]\n{}\n[SearchIgnore]\n.git/\nbuild/\n", settings)).unwrap();
+    }
+
+    fn files(&self) -> PathBuf {
+        self.dir.join("files")
+    }
+
+    /// Stores an indexer description the way the portal does
+    fn describe(&self, file: &str, description: &str) {
+        let db = self.dir.join("home").join(".cache").join("pikeru");
+        std::fs::create_dir_all(&db).unwrap();
+        let sql = rusqlite::Connection::open(db.join("index.db")).unwrap();
+        sql.execute("create table if not exists descriptions
+                    (fname text, dir text, description text, mtime real, tags text, user_description text)", ()).unwrap();
+        let path = self.files().join(file);
+        sql.execute("insert into descriptions (fname, dir, description, mtime) values (?1, ?2, ?3, 0)",
+                    (path.file_name().unwrap().to_str(), path.parent().unwrap().to_str(), description)).unwrap();
+    }
+
+    /// Stores image colors the way the picker does after making a thumbnail
+    fn palette(&self, file: &str, colors: &str) {
+        let db = self.dir.join("home").join(".cache").join("pikeru");
+        std::fs::create_dir_all(&db).unwrap();
+        let sql = rusqlite::Connection::open(db.join("index.db")).unwrap();
+        sql.execute("create table if not exists thumbinfo
+                    (dir text, fname text, mtime real, phash integer, palette text, primary key (dir, fname))", ()).unwrap();
+        let path = self.files().join(file);
+        sql.execute("insert into thumbinfo (dir, fname, mtime, phash, palette) values (?1, ?2, 0, 0, ?3)",
+                    (path.parent().unwrap().to_str(), path.file_name().unwrap().to_str(), colors)).unwrap();
+    }
+
+    fn search(&self, args: &[impl AsRef<OsStr>]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_pikeru"))
+            .arg("search")
+            .args(args)
+            .env("HOME", self.dir.join("home"))
+            .env("PWD", &self.dir)
+            .current_dir(&self.dir)
+            .output().unwrap()
+    }
+
+    /// Result paths relative to the file tree, best first
+    fn ranked(&self, args: &[&str]) -> Vec<String> {
+        let out = self.search(args);
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8(out.stdout).unwrap().lines().map(|line| {
+            let (score, path) = line.split_once('\t').unwrap();
+            score.parse::<i64>().unwrap();
+            Path::new(path).strip_prefix(self.files()).unwrap().to_string_lossy().to_string()
+        }).collect()
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn ranks_names_and_skips_ignored() {
+    let tree = Tree::new("names", &["beach.jpg", "sub/sunset_beach_house.png", "notes.txt",
+                                    "build/beach.o", ".git/beach", ".beach"]);
+    let ranked = tree.ranked(&["files", "--", "beach"]);
+    assert_eq!(ranked, ["beach.jpg", "sub/sunset_beach_house.png"]);
+}
+
+#[test]
+fn matches_indexed_descriptions() {
+    let tree = Tree::new("descriptions", &["IMG_0001.jpg", "IMG_0002.jpg", "lighthouse.jpg"]);
+    tree.describe("IMG_0002.jpg", "a lighthouse on a cliff at dusk");
+    let ranked = tree.ranked(&["files", "--", "lighthouse"]);
+    assert_eq!(ranked, ["lighthouse.jpg", "IMG_0002.jpg"]);
+
+    let out = tree.search(&["--output", "json", "files", "--", "cliff"]);
+    let json = String::from_utf8(out.stdout).unwrap();
+    assert!(json.starts_with("{\"query\":\"cliff\",\"results\":[{\"path\":"), "{}", json);
+    assert!(json.contains("\"description\":\"a lighthouse on a cliff at dusk\",\"tags\":null"), "{}", json);
+}
+
+#[test]
+fn no_match_exit_code() {
+    let tree = Tree::new("nomatch", &["a.txt"]);
+    assert!(tree.ranked(&["files", "--", "zebra"]).is_empty());
+    assert_eq!(tree.search(&["--exit-code", "files", "--", "zebra"]).status.code(), Some(1));
+    assert_eq!(tree.search(&["--exit-code", "files", "--", "a"]).status.code(), Some(0));
+}
+
+#[test]
+fn query_terms_narrow_results() {
+    let tree = Tree::new("terms", &["beach.jpg", "beach.png", "photos/beach.webp", "red car.txt", "IMG_0001.jpg", "clip.mp4"]);
+    std::fs::write(tree.files().join("big_beach.png"), vec![0; 3 << 20]).unwrap();
+    tree.describe("IMG_0001.jpg", "a red car parked at the beach");
+    assert_eq!(tree.ranked(&["files", "--", "beach ext:png"]), ["beach.png", "big_beach.png"]);
+    assert_eq!(tree.ranked(&["files", "--", "beach -ext:png -path:photos"]), ["beach.jpg", "IMG_0001.jpg"]);
+    assert_eq!(tree.ranked(&["files", "--", "size:>1M"]), ["big_beach.png"]);
+    assert_eq!(tree.ranked(&["files", "--", "type:video"]), ["clip.mp4"]);
+    assert_eq!(tree.ranked(&["files", "--", "\"red car\""]), ["IMG_0001.jpg", "red car.txt"]);
+    assert_eq!(tree.ranked(&["files", "--", "desc:\"red car\""]), ["IMG_0001.jpg"]);
+    assert_eq!(tree.ranked(&["files", "--", r"re:^IMG_\d+\.jpg$"]), ["IMG_0001.jpg"]);
+    // unfinished terms don't hide everything while typing
+    assert_eq!(tree.ranked(&["files", "--", "clip size:>"]), ["clip.mp4"]);
+}
+
+#[test]
+fn mtime_and_color_terms() {
+    let tree = Tree::new("mtime", &["new.jpg", "old.jpg", "notes.txt"]);
+    let month_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(30 * 86400);
+    std::fs::File::options().write(true).open(tree.files().join("old.jpg")).unwrap().set_modified(month_ago).unwrap();
+    let sorted = |args: &[&str]| { let mut r = tree.ranked(args); r.sort(); r };
+    assert_eq!(sorted(&["files", "--", "mtime:<7d"]), ["new.jpg", "notes.txt"]);
+    assert_eq!(sorted(&["files", "--", "mtime:>7d"]), ["old.jpg"]);
+    assert_eq!(sorted(&["files", "--", "-mtime:<7d"]), ["old.jpg"]);
+
+    tree.palette("new.jpg", "#008080,#ffffff");
+    tree.palette("old.jpg", "#ff0000,#000000");
+    assert_eq!(sorted(&["files", "--", "color:teal"]), ["new.jpg"]);
+    // images without the color, and files without a palette, are left
+    assert_eq!(sorted(&["files", "--", "-color:teal"]), ["notes.txt", "old.jpg"]);
+    assert_eq!(sorted(&["files", "--", "ext:jpg -color:#ff0000"]), ["new.jpg"]);
+}
+
+#[test]
+fn content_search_greps_text_files() {
+    let tree = Tree::new("content", &[]);
+    let files = tree.files();
+    std::fs::create_dir_all(files.join("sites")).unwrap();
+    std::fs::create_dir_all(files.join("build")).unwrap();
+    std::fs::write(files.join("sites").join("app.conf"), "server {\n    listen 80;\n    proxy_pass http://localhost:8080;\n}\n").unwrap();
+    std::fs::write(files.join("notes.md"), "Remember to set PROXY_PASS before deploying\n").unwrap();
+    std::fs::write(files.join("build").join("app.conf"), "proxy_pass http://ignored\n").unwrap();
+    std::fs::write(files.join("blob.bin"), b"\0\x01proxy_pass\0").unwrap();
+    std::fs::write(files.join("proxy_pass.txt"), "nothing to see\n").unwrap();
+    // reading a FIFO nothing writes to would never finish
+    assert!(Command::new("mkfifo").arg(files.join("proxy_pass.fifo")).status().unwrap().success());
+
+    let out = tree.search(&["--content", "files", "--", "proxy_pass"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let lines = String::from_utf8(out.stdout).unwrap().lines().map(|line| {
+        line.strip_prefix(&format!("{}/", files.display())).unwrap().to_string()
+    }).collect::<Vec<_>>();
+    assert_eq!(lines, ["notes.md:1:Remember to set PROXY_PASS before deploying",
+                       "sites/app.conf:3:proxy_pass http://localhost:8080;"]);
+
+    let out = tree.search(&["--content", "--output", "json", "files", "--", "localhost"]);
+    let json = String::from_utf8(out.stdout).unwrap();
+    assert!(json.contains("\"line\":3,\"snippet\":\"proxy_pass http://localhost:8080;\""), "{}", json);
+    assert_eq!(tree.search(&["--content", "--exit-code", "files", "--", "zebra"]).status.code(), Some(1));
+}
+
+#[test]
+fn walk_depth_and_symlinks() {
+    let tree = Tree::new("walk", &["a/beach1.jpg", "a/b/beach2.jpg", "a/b/c/beach3.jpg", "other/beach4.jpg"]);
+    std::os::unix::fs::symlink(tree.files().join("other"), tree.files().join("a").join("link")).unwrap();
+    let a = tree.files().join("a");
+    let a = a.to_str().unwrap();
+    let mut all = tree.ranked(&[a, "--", "beach"]);
+    all.sort();
+    assert_eq!(all, ["a/b/beach2.jpg", "a/b/c/beach3.jpg", "a/beach1.jpg", "a/link/beach4.jpg"]);
+
+    tree.settings("search_depth = 1\nfollow_symlinks = false");
+    let mut shallow = tree.ranked(&[a, "--", "beach"]);
+    shallow.sort();
+    assert_eq!(shallow, ["a/b/beach2.jpg", "a/beach1.jpg"]);
+}
+
+#[test]
+fn non_utf8_paths_round_trip() {
+    let tree = Tree::new("latin1", &[]);
+    // "café" in latin-1, which isn't valid UTF-8
+    let dir = tree.files().join(OsStr::from_bytes(b"caf\xe9"));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    // with a private use char like the ones non-UTF-8 bytes are kept as inside the picker
+    std::fs::write(dir.join("sub").join(OsStr::from_bytes(b"beach \xe9t\xe9 \xee\xbe\x80.jpg")), "").unwrap();
+    let out = tree.search(&[dir.as_os_str(), OsStr::new("--"), OsStr::new("beach")]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let expected = [dir.join("sub").as_os_str().as_bytes(), b"/beach \xe9t\xe9 \xee\xbe\x80.jpg\n"].concat();
+    assert!(out.stdout.ends_with(&expected), "{:?}", String::from_utf8_lossy(&out.stdout));
+
+    let out = tree.search(&[OsStr::new("--output"), OsStr::new("null"), dir.as_os_str(), OsStr::new("--"), OsStr::new("beach")]);
+    assert_eq!(out.stdout, [&expected[..expected.len() - 1], b"\0"].concat());
+}
@@ -18,6 +18,12 @@
 # The script should print the selected paths to the output path (argument #5),
 # one path per line.
 # If nothing is printed, then the operation is assumed to have been canceled.
+# A non-zero exit status tells the portal the picker failed.
+#
+# Any arguments after the fourth are pikeru options from the portal that limit which
+# files the calling application can be given. Pass them on so the picker hides the rest.
+#
+# PIKERU can be set to run a pikeru other than /usr/local/bin/pikeru, like a local build.
 #
 # Notes:
 # Chrome doesn't provide the previous path via portal so need to do that here.
@@ -27,12 +33,15 @@ multiple="$1"
 directory="$2"
 save="$3"
 path="$4"
+shift 4
 
 [ -z "$path" ] && path="$HOME"
 
 #echo "'$1' '$2' '$3' '$path' '$5'" >> /tmp/pk.log
 
-if [ $directory = 1 ]; then
+if [ $directory = 1 ] && [ $multiple = 1 ]; then
+    mode=dirs
+elif [ $directory = 1 ]; then
     mode=dir
 elif [ $multiple = 1 ]; then
     mode=files
@@ -43,26 +52,28 @@ else
 fi
 
 
-#pikerudir="$(dirname "$(readlink -f "$0")")"
-#cmd="$pikerudir/../target/release/pikeru -m $mode -t 'File Picker' -p \"$path\""
+#PIKERU="$(dirname "$(readlink -f "$0")")/../target/release/pikeru"
 
-cmd="/usr/local/bin/pikeru -m $mode -t 'File Picker' -p \"$path\""
+# an array rather than a command string to eval, so the path reaches pikeru byte for
+# byte and quotes, $() or backticks in a folder name an app suggested are never run
+cmd=("${PIKERU:-/usr/local/bin/pikeru}" -m "$mode" -t 'File Picker' -p "$path" "$@")
 
 # iced has a problem with crashing when no gpu is available so disable and retry if that happens
 [ -r "$HOME/.cache/pikeru/no_gpu" ] && export ICED_BACKEND=tiny-skia
-echo "$cmd" >> /tmp/pk.log
-output="$(eval "$cmd")"
-if [ $? = 139 ] && [ ! -r "$HOME/.cache/pikeru/no_gpu" ]; then
+printf '%q ' "${cmd[@]}" >> /tmp/pk.log
+echo >> /tmp/pk.log
+output="$("${cmd[@]}")"
+status=$?
+if [ $status = 139 ] && [ ! -r "$HOME/.cache/pikeru/no_gpu" ]; then
     echo "Iced GUI gpu library crashed. Fixing now..." >> /tmp/pk.log
     touch "$HOME/.cache/pikeru/no_gpu"
     export ICED_BACKEND=tiny-skia
-    output="$(eval "$cmd")"
+    output="$("${cmd[@]}")"
+    status=$?
 fi
 
-if [ ! -z "$POSTPROCESSOR" ] && [ -r "$POSTPROCESSOR" ] && [ ! -z "$POSTPROCESS_DIR" ]; then
-    mkdir -p "$POSTPROCESS_DIR"
-    echo "$output" | bash "$POSTPROCESSOR"
-else
-    echo "$output"
-fi
+# the portal runs the postprocessor itself, after checking what was picked against its policy
+echo "$output"
 
+# let the portal know if the picker crashed so it can use its fallback_cmd
+exit $status
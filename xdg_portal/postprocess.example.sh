@@ -1,8 +1,8 @@
 #!/bin/bash
 # point "postprocessor" in the xdg portal config to this file to enable automatic file conversions for uploads.
 # Postprocessor script must read lines of files from stdin and write lines of files to stdout.
-# POSTPROCESS_DIR is exported by the portal that invokes this script. Use it for output files if you don't want
-# the filepicker to use your temporary directory as the next starting location.
+# POSTPROCESS_DIR is exported by the portal that invokes this script and is new for each request. Output files
+# must be written there, the portal refuses any other path that wasn't one of the selected files.
 
 dir=${POSTPROCESS_DIR:-/tmp/pk_postprocess}
 mkdir -p $dir